@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use school_substitution_plan_alert_fuzz::check_tables;
+use school_substitution_plan_alert_fuzz::substitution_schedule::SubstitutionSchedule;
+
+fuzz_target!(|table: Vec<Vec<String>>| {
+	let _ = SubstitutionSchedule::table_to_substitutions(0, &table);
+	check_tables(&vec![table]);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use school_substitution_plan_alert_fuzz::{check_tables, tabula_json_parser};
+
+fuzz_target!(|content: &str| {
+	if let Ok(tables) = tabula_json_parser::parse_csv(content) {
+		check_tables(&tables);
+	}
+});
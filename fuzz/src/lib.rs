@@ -0,0 +1,28 @@
+//! The parsing modules of the bot, which is a binary crate, so the fuzz targets can use them.
+//! Run a target with `cargo +nightly fuzz run <target>`. Inputs that crashed go into `regressions/<format>`,
+//! where the property tests of the bot run them on every `cargo test`.
+
+#[path = "../../src/error.rs"]
+pub mod error;
+#[path = "../../src/pdf_table_extractor.rs"]
+pub mod pdf_table_extractor;
+#[path = "../../src/schedule_metadata.rs"]
+pub mod schedule_metadata;
+#[path = "../../src/school_time.rs"]
+pub mod school_time;
+#[path = "../../src/substitution_schedule.rs"]
+pub mod substitution_schedule;
+#[path = "../../src/tabula_json_parser.rs"]
+pub mod tabula_json_parser;
+
+use substitution_schedule::SubstitutionSchedule;
+
+/// Builds the schedule from the tables and checks that it survives a round trip through serde
+pub fn check_tables(tables: &Vec<Vec<Vec<String>>>) {
+	if let Ok(schedule) = SubstitutionSchedule::from_table(tables, 0) {
+		let json = serde_json::to_string(&schedule).unwrap();
+		let deserialized: SubstitutionSchedule = serde_json::from_str(&json).unwrap();
+
+		assert_eq!(serde_json::to_value(&schedule).unwrap(), serde_json::to_value(&deserialized).unwrap());
+	}
+}
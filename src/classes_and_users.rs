@@ -30,12 +30,11 @@ impl ClassesAndUsers {
 		self.datastore.store_classes_and_users(&self.classes_and_users)
 	}
 
-	#[allow(clippy::or_fun_call)]
 	pub fn insert_user(&mut self, class: String, user_id: u64) -> Result<(), Box<dyn Error>> {
 		self.
 			classes_and_users
 			.entry(class)
-			.or_insert(HashSet::new())
+			.or_default()
 			.insert(user_id);
 		self.save()
 	}
@@ -55,6 +54,23 @@ impl ClassesAndUsers {
 		Ok(successful)
 	}
 
+	/// Removes the user from all of their classes and returns them.
+	pub fn remove_user(&mut self, user_id: u64) -> Result<Vec<String>, Box<dyn Error>> {
+		let classes = self.get_user_classes(user_id);
+
+		for class in &classes {
+			if let Some(class_users) = self.classes_and_users.get_mut(class) {
+				class_users.remove(&user_id);
+				if class_users.is_empty() {
+					self.classes_and_users.remove(class);
+				}
+			}
+		}
+
+		self.save()?;
+		Ok(classes)
+	}
+
 	/// Gets the classes a user subscribed to.
 	pub fn get_user_classes(&self, user_id: u64) -> Vec<String> {
 		let mut classes = Vec::new();
@@ -66,6 +82,7 @@ impl ClassesAndUsers {
 			}
 		}
 
+		classes.sort();
 		classes
 	}
 
@@ -108,5 +125,10 @@ mod tests {
 		classes_and_users.remove_user_from_class(class, 1).unwrap();
 
 		assert_eq!(classes_and_users.get_user_classes(1), vec![class_2.to_owned()]);
+
+		assert_eq!(classes_and_users.remove_user(3).unwrap(), vec![class_2.to_owned()]);
+		assert!(classes_and_users.get_user_classes(3).is_empty());
+		assert_eq!(classes_and_users.get_user_classes(1), vec![class_2.to_owned()]);
+		assert_eq!(ClassesAndUsers::new(datastore).get_user_classes(3), Vec::<String>::new());
 	}
 }
\ No newline at end of file
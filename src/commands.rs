@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
 use log::{debug, error, info};
 use serenity::{
 	framework::standard::{
@@ -16,10 +17,15 @@ use serenity::model::prelude::{Activity, OnlineStatus, Ready, UserId};
 
 use crate::{Data, DataStore};
 use crate::classes_and_users::ClassesAndUsers;
+use crate::config::Config;
+use crate::data::ArchivedRevision;
+use crate::polling_scheduler::PollTrigger;
+use crate::schedule_source::ScheduleSourceContainer;
+use crate::school_time;
 use crate::util::sanitize_and_check_register_class_input;
 
 #[group]
-#[commands(register, show_classes, unregister)]
+#[commands(register, show_classes, unregister, source_status, history, poll)]
 pub struct General;
 
 #[command]
@@ -116,6 +122,135 @@ async fn unregister(ctx: &Context, msg: &Message, mut args: Args) -> CommandResu
 	Ok(())
 }
 
+#[command]
+#[owners_only]
+#[aliases("status", "health")]
+#[description("Shows how the downloads of the substitution plans went.")]
+async fn source_status(ctx: &Context, msg: &Message) -> CommandResult {
+	let data = ctx.data.read().await;
+	let pdf_getter = data.get::<ScheduleSourceContainer>().unwrap();
+	let timezone = data.get::<Config>().unwrap().general.timezone;
+	let format_time = |time: Option<DateTime<Utc>>| time.map_or("never".to_owned(), |time| time.with_timezone(&timezone).format("%d.%m.%Y %H:%M:%S").to_string());
+
+	let status = pdf_getter.health()
+		.into_iter()
+		.map(|(day, health)| {
+			let mut status = format!("**{}**: last success {}", day, format_time(health.last_success));
+			if health.consecutive_failures > 0 {
+				status.push_str(&format!(
+					", {} failures since, last at {}: {}",
+					health.consecutive_failures,
+					format_time(health.last_error_time),
+					health.last_error.unwrap_or_default()
+				));
+			}
+			status
+		})
+		.collect::<Vec<String>>();
+
+	msg.channel_id.send_message(&ctx.http, |msg|
+		msg.embed(|embed| {
+			embed.description(
+				if status.is_empty() {
+					"Nothing was downloaded yet".to_owned()
+				} else {
+					status.join("\n")
+				}
+			)
+		}),
+	).await?;
+
+	Ok(())
+}
+
+#[command]
+#[owners_only]
+#[aliases("poll_now", "check")]
+#[description("Checks the substitution plans right away instead of waiting for the next check.")]
+async fn poll(ctx: &Context, msg: &Message) -> CommandResult {
+	let data = ctx.data.read().await;
+	data.get::<PollTrigger>().unwrap().fire();
+
+	msg.reply(&ctx.http, "Checking the substitution plans now").await?;
+
+	Ok(())
+}
+
+#[command]
+#[owners_only]
+#[aliases("revisions", "archive")]
+#[description("Lists the archived revisions of the plan of a date, or sends the one that was the latest at a time.")]
+#[example("18.02.2020")]
+#[example("18.02.2020 07:02")]
+#[min_args(1)]
+#[max_args(2)]
+async fn history(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+	let date = match NaiveDate::parse_from_str(&args.single::<String>()?, "%d.%m.%Y") {
+		Ok(date) => date,
+		Err(_) => {
+			msg.reply_ping(&ctx.http, "The date has to look like 18.02.2020").await?;
+			return Ok(());
+		}
+	};
+	let data = ctx.data.read().await;
+	let datastore = data.get::<Data>().unwrap();
+	let timezone = data.get::<Config>().unwrap().general.timezone;
+
+	let time = match args.single::<String>().ok().map(|time| NaiveTime::parse_from_str(&time, "%H:%M")) {
+		Some(Ok(time)) => Some(school_time::local_to_millis(date.and_time(time), timezone)),
+		Some(Err(_)) => {
+			msg.reply_ping(&ctx.http, "The time has to look like 07:02").await?;
+			return Ok(());
+		}
+		None => None,
+	};
+	let date_millis = school_time::date_to_millis(date);
+	let format_time = |millis: i64| timezone.timestamp_millis(millis).format("%d.%m.%Y %H:%M:%S").to_string();
+
+	let time = match time {
+		Some(time) => time,
+		None => {
+			let revisions = datastore.get_revisions(date_millis).map_err(|why| why.to_string())?;
+			let list = revisions.iter()
+				.map(|revision| format!("{}: `{}`", format_time(revision.fetched_at), revision.sha256))
+				.collect::<Vec<String>>();
+
+			msg.channel_id.send_message(&ctx.http, |msg|
+				msg.embed(|embed| {
+					embed.title(format!("Revisions of {}", date.format("%d.%m.%Y")));
+					embed.description(
+						if list.is_empty() {
+							"Nothing was archived for this date".to_owned()
+						} else {
+							list.join("\n")
+						}
+					)
+				}),
+			).await?;
+			return Ok(());
+		}
+	};
+
+	let revision = datastore.get_revision_at(date_millis, time).map_err(|why| why.to_string())?;
+	let ArchivedRevision { sha256, fetched_at, .. } = match revision {
+		Some(revision) => revision,
+		None => {
+			msg.reply_ping(&ctx.http, format!("There was no plan for {} yet at {}", date.format("%d.%m.%Y"), format_time(time))).await?;
+			return Ok(());
+		}
+	};
+	let pdf = datastore.get_archived_pdf(&sha256).map_err(|why| why.to_string())?;
+	let schedule_json = datastore.get_archived_schedule_json(&sha256).map_err(|why| why.to_string())?;
+
+	msg.channel_id.send_files(
+		&ctx.http,
+		vec![(pdf.as_slice(), format!("{}.pdf", sha256).as_str()), (schedule_json.as_bytes(), format!("{}.json", sha256).as_str())],
+		|msg| msg.content(format!("The plan for {} fetched at {}", date.format("%d.%m.%Y"), format_time(fetched_at))),
+	).await?;
+
+	Ok(())
+}
+
 #[hook]
 pub async fn before(_ctx: &Context, msg: &Message, command_name: &str) -> bool {
 	info!("Got command '{}' by user '{}'", command_name, msg.author.name);
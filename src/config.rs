@@ -2,14 +2,42 @@ use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
 
-use serde::Deserialize;
+use chrono::{NaiveDate, NaiveTime, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
 use serenity::model::prelude::UserId;
 use serenity::prelude::TypeMapKey;
 
+use crate::school_time;
+
+/// The environment variables that override the credentials in the config file
+const USERNAME_ENV: &str = "SOURCE_USERNAME";
+const PASSWORD_ENV: &str = "SOURCE_PASSWORD";
+const TOKEN_ENV: &str = "SOURCE_TOKEN";
+/// The basic auth credentials the bot sent before they could be configured
+const DEFAULT_USERNAME: &str = "hbsuser";
+const DEFAULT_PASSWORD: &str = "hbspass";
+
 /// This struct holds the other more specific config structs
 #[derive(Deserialize)]
 pub struct Config {
 	pub general: General,
+	/// Where the PDFs are downloaded from, the defaults are used if the section is missing
+	#[serde(default)]
+	pub source: Source,
+	/// How long the downloaded plans are kept, the defaults are used if the section is missing
+	#[serde(default)]
+	pub archive: Archive,
+	/// How the users are notified, the defaults are used if the section is missing
+	#[serde(default)]
+	pub notifications: Notifications,
+	/// When the plans are checked, the defaults are used if the section is missing
+	#[serde(default)]
+	pub polling: Polling,
+	/// The days without school, none if the section is missing
+	#[serde(default)]
+	pub holidays: Holidays,
 }
 
 /// The struct for general config stuff. More specific functionality, specific functionality like
@@ -28,12 +56,24 @@ pub struct General {
 	/// Pre defined classes, these are loaded into the whitelist on startup.
 	#[serde(default)]
 	pub class_whitelist: HashSet<String>,
+	/// The timezone of the school, e.g. "Europe/Berlin", all dates and times are in it
+	#[serde(default = "timezone_default", deserialize_with = "deserialize_timezone")]
+	pub timezone: Tz,
 }
 
 fn prefix_default() -> String {
 	"~".to_owned()
 }
 
+fn timezone_default() -> Tz {
+	school_time::DEFAULT_TIMEZONE
+}
+
+fn deserialize_timezone<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Tz, D::Error> {
+	let timezone = String::deserialize(deserializer)?;
+	timezone.parse().map_err(|_| D::Error::custom(format!("Unknown timezone '{}'", timezone)))
+}
+
 impl Default for General {
 	fn default() -> Self {
 		Self {
@@ -41,6 +81,372 @@ impl Default for General {
 			prefix: "~".to_owned(),
 			owners: HashSet::new(),
 			class_whitelist: HashSet::new(),
+			timezone: timezone_default(),
+		}
+	}
+}
+
+/// Where and how the substitution PDFs are downloaded
+#[derive(Deserialize, Debug, Clone)]
+pub struct Source {
+	/// Where the PDFs come from, "http" by default
+	#[serde(default)]
+	pub kind: SourceKind,
+	/// The directory of the "directory" and "replay" sources
+	pub path: Option<String>,
+	/// The URL of the PDF of a day, `{weekday}` is replaced with the German name of the weekday, e.g. "Montag"
+	#[serde(default = "url_template_default")]
+	pub url_template: String,
+	/// How to authenticate, basic auth with the credentials the bot always sent by default
+	#[serde(default = "auth_default")]
+	pub auth: AuthType,
+	/// The user name for basic auth, can also be set with the `SOURCE_USERNAME` environment variable
+	#[serde(default = "username_default")]
+	pub username: Option<String>,
+	/// The password for basic auth, can also be set with the `SOURCE_PASSWORD` environment variable
+	#[serde(default = "password_default")]
+	pub password: Option<String>,
+	/// The token for bearer auth, can also be set with the `SOURCE_TOKEN` environment variable
+	pub token: Option<String>,
+	#[serde(default = "user_agent_default")]
+	pub user_agent: String,
+	/// The timeout for connecting in seconds
+	#[serde(default = "timeout_default")]
+	pub connect_timeout: u64,
+	/// The timeout for the whole request in seconds
+	#[serde(default = "timeout_default")]
+	pub timeout: u64,
+	/// How often a download that failed for a transient reason is tried again
+	#[serde(default = "retries_default")]
+	pub retries: u32,
+	/// The delay before the first retry in milliseconds, it doubles with every retry
+	#[serde(default = "retry_delay_default")]
+	pub retry_delay: u64,
+	/// The maximum delay between two retries in milliseconds
+	#[serde(default = "max_retry_delay_default")]
+	pub max_retry_delay: u64,
+}
+
+/// The archive of every distinct plan in the data directory
+#[derive(Deserialize, Debug, Clone)]
+pub struct Archive {
+	/// The revisions of dates older than this many days are removed
+	#[serde(default = "retention_days_default")]
+	pub retention_days: u32,
+}
+
+fn retention_days_default() -> u32 {
+	90
+}
+
+impl Default for Archive {
+	fn default() -> Self {
+		Self {
+			retention_days: retention_days_default(),
+		}
+	}
+}
+
+/// The notifications sent to the users
+#[derive(Deserialize, Debug, Clone)]
+pub struct Notifications {
+	/// The log of sent notifications, which prevents sending the same one twice, forgets the dates older than this many days
+	#[serde(default = "log_retention_days_default")]
+	pub log_retention_days: u32,
+	/// How often a DM is tried before it's given up on
+	#[serde(default = "max_attempts_default")]
+	pub max_attempts: u32,
+	/// The delay in seconds before a failed DM is tried again, doubling with every attempt
+	#[serde(default = "dm_retry_delay_default")]
+	pub retry_delay: u64,
+	/// Users are unsubscribed after this many DMs in a row couldn't be delivered, e.g. because they blocked the bot
+	#[serde(default = "unsubscribe_after_default")]
+	pub unsubscribe_after: u32,
+	/// The pause in milliseconds between two DMs, so the bot stays clear of the rate limits of Discord
+	#[serde(default = "send_delay_default")]
+	pub send_delay: u64,
+	/// A new plan is only notified about once it stayed the same for this many minutes, 0 notifies right away
+	#[serde(default)]
+	pub debounce_minutes: u64,
+	/// Unless a changed block starts within this many minutes, then the users are notified right away
+	#[serde(default = "urgent_minutes_default")]
+	pub urgent_minutes: u64,
+}
+
+fn log_retention_days_default() -> u32 {
+	14
+}
+
+fn max_attempts_default() -> u32 {
+	5
+}
+
+fn dm_retry_delay_default() -> u64 {
+	60
+}
+
+fn unsubscribe_after_default() -> u32 {
+	3
+}
+
+fn send_delay_default() -> u64 {
+	1000
+}
+
+fn urgent_minutes_default() -> u64 {
+	60
+}
+
+impl Default for Notifications {
+	fn default() -> Self {
+		Self {
+			log_retention_days: log_retention_days_default(),
+			max_attempts: max_attempts_default(),
+			retry_delay: dm_retry_delay_default(),
+			unsubscribe_after: unsubscribe_after_default(),
+			send_delay: send_delay_default(),
+			debounce_minutes: 0,
+			urgent_minutes: urgent_minutes_default(),
+		}
+	}
+}
+
+/// How often the plans are checked, the first rule matching the current time decides
+#[derive(Deserialize, Debug, Clone)]
+pub struct Polling {
+	/// The interval in seconds when no rule matches
+	#[serde(default = "default_interval_default")]
+	pub default_interval: u64,
+	/// Up to this many seconds are added to every interval at random, so the checks don't always hit the server at the same second
+	#[serde(default)]
+	pub jitter: u64,
+	#[serde(default = "rules_default")]
+	pub rules: Vec<PollingRule>,
+	/// How many school days are checked, starting with today or the next school day on weekends
+	#[serde(default = "lookahead_days_default")]
+	pub lookahead_days: usize,
+	/// How many days are checked at the same time
+	#[serde(default = "max_concurrent_checks_default")]
+	pub max_concurrent_checks: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PollingRule {
+	/// The days the rule applies on, e.g. ["mon", "tue"], every day if empty
+	#[serde(default, deserialize_with = "deserialize_weekdays")]
+	pub days: Vec<Weekday>,
+	/// Whether the rule only applies on school days, so not on weekends and holidays
+	#[serde(default)]
+	pub school_days: bool,
+	/// The start of the time range, e.g. "06:00"
+	#[serde(deserialize_with = "deserialize_time")]
+	pub from: NaiveTime,
+	/// The end of the time range, exclusive. If it's before `from`, the range goes over midnight.
+	#[serde(deserialize_with = "deserialize_time")]
+	pub to: NaiveTime,
+	/// The interval in seconds
+	pub interval: u64,
+}
+
+fn default_interval_default() -> u64 {
+	3600
+}
+
+fn lookahead_days_default() -> usize {
+	5
+}
+
+fn max_concurrent_checks_default() -> usize {
+	2
+}
+
+/// Every 20 seconds before school on school days, when most changes are published, and every 5 minutes during the day
+fn rules_default() -> Vec<PollingRule> {
+	vec![
+		PollingRule {
+			days: Vec::new(),
+			school_days: true,
+			from: NaiveTime::from_hms(6, 0, 0),
+			to: NaiveTime::from_hms(8, 30, 0),
+			interval: 20,
+		},
+		PollingRule {
+			days: Vec::new(),
+			school_days: true,
+			from: NaiveTime::from_hms(8, 30, 0),
+			to: NaiveTime::from_hms(22, 0, 0),
+			interval: 300,
+		},
+	]
+}
+
+impl Default for Polling {
+	fn default() -> Self {
+		Self {
+			default_interval: default_interval_default(),
+			jitter: 0,
+			rules: rules_default(),
+			lookahead_days: lookahead_days_default(),
+			max_concurrent_checks: max_concurrent_checks_default(),
+		}
+	}
+}
+
+/// The holidays from a list and an iCalendar file, e.g. the school holidays published by the ministry
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Holidays {
+	pub ics_file: Option<String>,
+	#[serde(default)]
+	pub periods: Vec<HolidayPeriod>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct HolidayPeriod {
+	#[serde(default)]
+	pub name: String,
+	/// The first day, e.g. "2020-12-21"
+	#[serde(deserialize_with = "deserialize_date")]
+	pub from: NaiveDate,
+	/// The last day, inclusive. The period is only `from` if it's missing.
+	#[serde(default, deserialize_with = "deserialize_optional_date")]
+	pub to: Option<NaiveDate>,
+}
+
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+	let date = String::deserialize(deserializer)?;
+	NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|_| D::Error::custom(format!("The date '{}' has to look like 2020-12-21", date)))
+}
+
+fn deserialize_optional_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<NaiveDate>, D::Error> {
+	deserialize_date(deserializer).map(Some)
+}
+
+fn deserialize_weekdays<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Weekday>, D::Error> {
+	Vec::<String>::deserialize(deserializer)?
+		.iter()
+		.map(|day| day.parse::<Weekday>().map_err(|_| D::Error::custom(format!("Unknown weekday '{}'", day))))
+		.collect()
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+	let time = String::deserialize(deserializer)?;
+	NaiveTime::parse_from_str(&time, "%H:%M").map_err(|_| D::Error::custom(format!("The time '{}' has to look like 06:00", time)))
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+	/// Downloads the PDFs from `url_template`
+	#[default]
+	Http,
+	/// Reads the PDFs from `<path>/<Weekday>.pdf`, e.g. "Monday.pdf", for manual operation
+	Directory,
+	/// Walks through the PDFs in `path` sorted by name, for demos and tests
+	Replay,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthType {
+	#[default]
+	None,
+	Basic,
+	Bearer,
+}
+
+/// The credentials to send with every request, resolved from the config and the environment
+#[derive(Debug, Clone, PartialEq)]
+pub enum Auth {
+	None,
+	Basic {
+		username: String,
+		password: Option<String>,
+	},
+	Bearer(String),
+}
+
+fn url_template_default() -> String {
+	"https://buessing.schule/plaene/VertretungsplanA4_{weekday}.pdf".to_owned()
+}
+
+fn auth_default() -> AuthType {
+	AuthType::Basic
+}
+
+fn username_default() -> Option<String> {
+	Some(DEFAULT_USERNAME.to_owned())
+}
+
+fn password_default() -> Option<String> {
+	Some(DEFAULT_PASSWORD.to_owned())
+}
+
+fn user_agent_default() -> String {
+	format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+fn timeout_default() -> u64 {
+	20
+}
+
+fn retries_default() -> u32 {
+	3
+}
+
+fn retry_delay_default() -> u64 {
+	1000
+}
+
+fn max_retry_delay_default() -> u64 {
+	10_000
+}
+
+/// The credentials the bot always sent are used unless the config sets others, so existing configs keep working
+impl Default for Source {
+	fn default() -> Self {
+		Self {
+			kind: SourceKind::default(),
+			path: None,
+			url_template: url_template_default(),
+			auth: auth_default(),
+			username: username_default(),
+			password: password_default(),
+			token: None,
+			user_agent: user_agent_default(),
+			connect_timeout: timeout_default(),
+			timeout: timeout_default(),
+			retries: retries_default(),
+			retry_delay: retry_delay_default(),
+			max_retry_delay: max_retry_delay_default(),
+		}
+	}
+}
+
+impl Source {
+	/// The URL of the PDF for the day
+	pub fn url(&self, weekday: &str) -> String {
+		self.url_template.replace("{weekday}", weekday)
+	}
+
+	/// Gets the credentials, the environment variables take precedence over the config file.
+	/// Returns an error naming the missing value if the auth type needs credentials that aren't set.
+	pub fn auth(&self) -> Result<Auth, String> {
+		self.auth_with_env(|name| std::env::var(name).ok())
+	}
+
+	fn auth_with_env(&self, env: impl Fn(&str) -> Option<String>) -> Result<Auth, String> {
+		let value = |env_name: &str, config_value: &Option<String>| env(env_name).or_else(|| config_value.clone());
+
+		match self.auth {
+			AuthType::None => Ok(Auth::None),
+			AuthType::Basic => Ok(Auth::Basic {
+				username: value(USERNAME_ENV, &self.username)
+					.ok_or_else(|| format!("Basic auth needs a username, set `username` in [source] or {}", USERNAME_ENV))?,
+				password: value(PASSWORD_ENV, &self.password),
+			}),
+			AuthType::Bearer => value(TOKEN_ENV, &self.token)
+				.map(Auth::Bearer)
+				.ok_or_else(|| format!("Bearer auth needs a token, set `token` in [source] or {}", TOKEN_ENV)),
 		}
 	}
 }
@@ -69,12 +475,15 @@ mod tests {
 
 	use serenity::model::id::UserId;
 
+	use super::*;
+
 	#[test]
 	fn test_parse_config() {
 		let config_str = r"
 		[general]
 		discord_token = 'test_token'
 		prefix = '-'
+		timezone = 'UTC'
 		owners = [191594115907977225, 276431762815451138, 325704347767799808]
 		class_whitelist = [
     		'Class 1',
@@ -95,7 +504,164 @@ mod tests {
 
 		assert_eq!(config.general.discord_token, "test_token");
 		assert_eq!(config.general.prefix, "-");
+		assert_eq!(config.general.timezone, chrono_tz::UTC);
 		assert_eq!(owners, config.general.owners);
 		assert_eq!(classes, config.general.class_whitelist)
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_parse_source() {
+		let config_str = r"
+		[general]
+		discord_token = 'test_token'
+
+		[source]
+		url_template = 'https://staging.example.com/{weekday}.pdf'
+		auth = 'basic'
+		username = 'user'
+		password = 'password'
+		timeout = 5
+		";
+
+		let config = Config::from_str(config_str);
+
+		assert_eq!(config.source.url("Montag"), "https://staging.example.com/Montag.pdf");
+		assert_eq!(config.source.auth, AuthType::Basic);
+		assert_eq!(config.source.timeout, 5);
+		assert_eq!(config.source.connect_timeout, 20);
+		assert_eq!(config.source.auth_with_env(|_| None), Ok(Auth::Basic {
+			username: "user".to_owned(),
+			password: Some("password".to_owned()),
+		}));
+	}
+
+	#[test]
+	fn test_source_defaults() {
+		let config = Config::from_str("[general]\ndiscord_token = 'test_token'");
+
+		assert_eq!(config.source.url("Freitag"), "https://buessing.schule/plaene/VertretungsplanA4_Freitag.pdf");
+		assert_eq!(config.source.auth_with_env(|_| None), Ok(Auth::Basic {
+			username: "hbsuser".to_owned(),
+			password: Some("hbspass".to_owned()),
+		}));
+		assert_eq!(config.source.kind, SourceKind::Http);
+		assert_eq!(config.general.timezone, chrono_tz::Europe::Berlin);
+		assert_eq!(config.archive.retention_days, 90);
+		assert_eq!(config.notifications.log_retention_days, 14);
+		assert_eq!(config.notifications.unsubscribe_after, 3);
+		assert_eq!(config.notifications.debounce_minutes, 0);
+		assert_eq!(config.polling.rules, rules_default());
+		assert_eq!(config.polling.lookahead_days, 5);
+
+	}
+
+	#[test]
+	fn test_partial_source_section() {
+		// e.g. pointing the bot at a staging mirror keeps the credentials
+		let config = Config::from_str("[general]\ndiscord_token = 'test_token'\n[source]\nurl_template = 'https://staging.example.com/{weekday}.pdf'\ntimeout = 5");
+		assert_eq!(config.source.url("Freitag"), "https://staging.example.com/Freitag.pdf");
+		assert_eq!(config.source.auth_with_env(|_| None), Ok(Auth::Basic {
+			username: "hbsuser".to_owned(),
+			password: Some("hbspass".to_owned()),
+		}));
+
+		let config = Config::from_str("[general]\ndiscord_token = 'test_token'\n[source]\nauth = 'none'");
+		assert_eq!(config.source.auth_with_env(|_| None), Ok(Auth::None));
+
+		let config = Config::from_str("[general]\ndiscord_token = 'test_token'\n[source]\nauth = 'bearer'\ntoken = 'secret'");
+		assert_eq!(config.source.auth_with_env(|_| None), Ok(Auth::Bearer("secret".to_owned())));
+	}
+
+	#[test]
+	fn test_parse_polling() {
+		let config_str = r"
+		[general]
+		discord_token = 'test_token'
+
+		[polling]
+		default_interval = 600
+		jitter = 5
+
+		[[polling.rules]]
+		days = ['sat', 'Sunday']
+		from = '22:00'
+		to = '06:00'
+		interval = 7200
+		";
+
+		let config = Config::from_str(config_str);
+
+		assert_eq!(config.polling.default_interval, 600);
+		assert_eq!(config.polling.jitter, 5);
+		assert_eq!(config.polling.rules, vec![PollingRule {
+			days: vec![Weekday::Sat, Weekday::Sun],
+			school_days: false,
+			from: NaiveTime::from_hms(22, 0, 0),
+			to: NaiveTime::from_hms(6, 0, 0),
+			interval: 7200,
+		}]);
+	}
+
+	#[test]
+	fn test_parse_holidays() {
+		let config_str = r"
+		[general]
+		discord_token = 'test_token'
+
+		[holidays]
+		ics_file = 'ferien.ics'
+
+		[[holidays.periods]]
+		name = 'Weihnachtsferien'
+		from = '2020-12-21'
+		to = '2021-01-08'
+
+		[[holidays.periods]]
+		from = '2021-05-13'
+		";
+
+		let config = Config::from_str(config_str);
+
+		assert_eq!(config.holidays.ics_file.as_deref(), Some("ferien.ics"));
+		assert_eq!(config.holidays.periods, vec![
+			HolidayPeriod {
+				name: "Weihnachtsferien".to_owned(),
+				from: NaiveDate::from_ymd(2020, 12, 21),
+				to: Some(NaiveDate::from_ymd(2021, 1, 8)),
+			},
+			HolidayPeriod {
+				name: String::new(),
+				from: NaiveDate::from_ymd(2021, 5, 13),
+				to: None,
+			},
+		]);
+	}
+
+	#[test]
+	fn test_parse_source_kind() {
+		let config = Config::from_str("[general]\ndiscord_token = 'test_token'\n[source]\nkind = 'replay'\npath = './sample_plans'");
+
+		assert_eq!(config.source.kind, SourceKind::Replay);
+		assert_eq!(config.source.path.as_deref(), Some("./sample_plans"));
+	}
+
+	#[test]
+	fn test_source_auth_from_env() {
+		let source = Source {
+			auth: AuthType::Bearer,
+			token: Some("from config".to_owned()),
+			..Source::default()
+		};
+		let env = |name: &str| (name == TOKEN_ENV).then(|| "from env".to_owned());
+
+		assert_eq!(source.auth_with_env(env), Ok(Auth::Bearer("from env".to_owned())));
+		assert_eq!(source.auth_with_env(|_| None), Ok(Auth::Bearer("from config".to_owned())));
+
+		let source = Source {
+			auth: AuthType::Basic,
+			username: None,
+			..Source::default()
+		};
+		assert!(source.auth_with_env(|_| None).is_err());
+	}
+}
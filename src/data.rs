@@ -5,22 +5,104 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
-use crate::substitution_pdf_getter::Weekdays;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::school_time;
 use crate::TypeMapKey;
 
+/// One file per date the plan is for, e.g. "2021-11-15.json"
 const PDF_JSON_DIR_NAME: &str = "pdf_jsons";
+const DATE_FILE_FORMAT: &str = "%Y-%m-%d";
+/// Every distinct PDF with its parsed schedule, named by the SHA-256 of the PDF, and the revisions of every date
+const ARCHIVE_DIR_NAME: &str = "archive";
+const ARCHIVE_PDF_DIR_NAME: &str = "pdfs";
+const ARCHIVE_SCHEDULE_DIR_NAME: &str = "schedules";
+/// One file per date, e.g. "2020-02-18.json", listing its revisions in the order they were fetched
+const ARCHIVE_REVISION_DIR_NAME: &str = "revisions";
+/// One file per date, e.g. "2020-02-18.json", listing the notifications about its plan in the order they were sent
+const NOTIFICATION_LOG_DIR_NAME: &str = "notification_log";
+/// The DMs that weren't sent yet
+const OUTBOX_FILE_NAME: &str = "outbox.json";
+/// The latest revisions of the dates that are waiting for their plan to stop changing before the users are notified
+const PENDING_REVISIONS_FILE_NAME: &str = "pending_revisions.json";
+/// How many DMs in a row couldn't be delivered to a user
+const DM_FAILURES_FILE_NAME: &str = "dm_failures.json";
 const WHITELIST_JSON_FILE_NAME: &str = "class_whitelist.json";
 const CLASSES_AND_USERS_FILE_NAME: &str = "class_registry.json";
 
 pub struct Data {
 	data_directory: String,
 	whitelist_file: Mutex<File>,
+	/// Held while the archive is written or pruned, so pruning doesn't remove a PDF of a revision that is being stored
+	archive_lock: Mutex<()>,
+	/// Held while the notification log is written or pruned, the days are checked at the same time
+	notification_log_lock: Mutex<()>,
+	/// Held while the pending revisions are changed, the days are checked at the same time
+	pending_lock: Mutex<()>,
+}
+
+/// A version of the plan of a date as it was fetched
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchivedRevision {
+	/// The SHA-256 of the PDF, the name of the archived PDF and schedule
+	pub sha256: String,
+	/// When the PDF was fetched in milliseconds
+	pub fetched_at: i64,
+	/// The date the plan is for, like `SubstitutionSchedule::pdf_create_date`
+	pub date: i64,
+}
+
+/// A revision that is waiting for its plan to stop changing before the users are notified about it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingRevision {
+	#[serde(flatten)]
+	pub revision: ArchivedRevision,
+	/// The schedule parsed from the PDF when it was fetched. The archive only has the schedule of the first time
+	/// the PDF was archived, if writing it worked. Missing in the revisions that were pending before it was kept here
+	#[serde(default)]
+	pub schedule_json: Option<String>,
+}
+
+/// A notification about the plan of a class that was sent to a user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SentNotification {
+	pub user_id: u64,
+	/// The date the plan is for, like `SubstitutionSchedule::pdf_create_date`
+	pub date: i64,
+	pub class: String,
+	/// Identifies what the user was told about the class, the same hash means the same content
+	pub content_hash: String,
+	/// When the notification was sent in milliseconds
+	pub sent_at: i64,
+}
+
+/// A DM waiting in the outbox until it is sent or given up on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutboxMessage {
+	pub user_id: u64,
+	/// The date the plan is for, a newer message about the same date replaces this one
+	pub date: i64,
+	pub content: String,
+	/// Logged once the message is sent
+	pub notifications: Vec<SentNotification>,
+	/// How often sending the message failed
+	pub attempts: u32,
+	/// When the message is sent next in milliseconds
+	pub next_attempt_at: i64,
+	/// How many of the parts of a message too long for one DM were already sent
+	#[serde(default)]
+	pub sent_parts: usize,
 }
 
 impl Data {
 	pub fn new(data_directory: String) -> Result<Self, Box<dyn Error>> {
 		std::fs::create_dir_all(data_directory.as_str())?;
 		std::fs::create_dir_all(format!("{}/{}", data_directory, PDF_JSON_DIR_NAME))?;
+		std::fs::create_dir_all(format!("{}/{}", data_directory, NOTIFICATION_LOG_DIR_NAME))?;
+		for dir in &[ARCHIVE_PDF_DIR_NAME, ARCHIVE_SCHEDULE_DIR_NAME, ARCHIVE_REVISION_DIR_NAME] {
+			std::fs::create_dir_all(format!("{}/{}/{}", data_directory, ARCHIVE_DIR_NAME, dir))?;
+		}
 
 		let whitelist_file = std::fs::OpenOptions::new()
 			.read(true)
@@ -31,8 +113,77 @@ impl Data {
 		Ok(Self {
 			data_directory,
 			whitelist_file: Mutex::new(whitelist_file),
+			archive_lock: Mutex::new(()),
+			notification_log_lock: Mutex::new(()),
+			pending_lock: Mutex::new(()),
 		})
 	}
+
+	/// The plans used to be stored by weekday, e.g. "Monday.json", which can't be told apart from the ones of other weeks.
+	/// They are moved to the date of their plan, so the users aren't notified about them again as if they were new.
+	/// Plans of past days and files that can't be read are removed.
+	pub fn migrate_weekday_pdf_jsons(&self, today: NaiveDate) -> Result<(), Box<dyn Error>> {
+		/// The only field of the old plans that is needed to find their date
+		#[derive(Deserialize)]
+		struct WeekdayPdfJson {
+			pdf_create_date: i64,
+		}
+
+		for entry in std::fs::read_dir(format!("{}/{}", self.data_directory, PDF_JSON_DIR_NAME))? {
+			let path = entry?.path();
+			if Self::date_of_pdf_json(&path).is_some() {
+				continue;
+			}
+
+			let date = std::fs::read_to_string(&path).ok()
+				.and_then(|content| serde_json::from_str::<WeekdayPdfJson>(&content).ok())
+				.map(|pdf_json| school_time::millis_to_date(pdf_json.pdf_create_date));
+			match date {
+				Some(date) if date >= today && !Path::new(&self.pdf_json_path(date)).exists() => {
+					log::info!("Moving {:?} to the plan for {}", path, date);
+					std::fs::rename(&path, self.pdf_json_path(date))?;
+				}
+				_ => {
+					log::info!("Removing {:?}, which isn't stored by date and isn't needed anymore", path);
+					std::fs::remove_file(path)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn pdf_json_path(&self, date: NaiveDate) -> String {
+		format!("{}/{}/{}.json", self.data_directory, PDF_JSON_DIR_NAME, date.format(DATE_FILE_FORMAT))
+	}
+
+	fn date_of_pdf_json(path: &Path) -> Option<NaiveDate> {
+		NaiveDate::parse_from_str(&path.file_stem()?.to_string_lossy(), DATE_FILE_FORMAT).ok()
+	}
+
+	fn archive_path(&self, dir: &str, file_name: &str) -> String {
+		format!("{}/{}/{}/{}", self.data_directory, ARCHIVE_DIR_NAME, dir, file_name)
+	}
+
+	/// The revision file of the date, e.g. "2020-02-18.json"
+	fn revisions_path(&self, date: i64) -> String {
+		let date = school_time::millis_to_date(date);
+		self.archive_path(ARCHIVE_REVISION_DIR_NAME, &format!("{}.json", date.format(DATE_FILE_FORMAT)))
+	}
+
+	/// The notification log file of the date, e.g. "2020-02-18.json"
+	fn notification_log_path(&self, date: i64) -> String {
+		let date = school_time::millis_to_date(date);
+		format!("{}/{}/{}.json", self.data_directory, NOTIFICATION_LOG_DIR_NAME, date.format(DATE_FILE_FORMAT))
+	}
+}
+
+/// Reads a JSON list, a missing file is an empty list
+fn read_json_list<T: serde::de::DeserializeOwned>(path: &str) -> Result<Vec<T>, Box<dyn Error>> {
+	match std::fs::read_to_string(path) {
+		Ok(content) => Ok(serde_json::from_str(&content)?),
+		Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+		Err(why) => Err(why.into()),
+	}
 }
 
 impl TypeMapKey for Data {
@@ -41,12 +192,12 @@ impl TypeMapKey for Data {
 
 impl DataStore for Data {
 	/// Stores the given PDF Json in a file
-	fn store_pdf_json(&self, weekday: Weekdays, pdf_json: &str) -> Result<(), Box<dyn Error>> {
+	fn store_pdf_json(&self, date: NaiveDate, pdf_json: &str) -> Result<(), Box<dyn Error>> {
 		let mut substitution_file = OpenOptions::new()
 			.write(true)
 			.create(true)
 			.truncate(true)
-			.open(format!("{}/{}/{}.json", self.data_directory, PDF_JSON_DIR_NAME, weekday))
+			.open(self.pdf_json_path(date))
 			.expect("Couldn't open file to write new json");
 
 		substitution_file.write_all(pdf_json.as_bytes())?;
@@ -55,9 +206,9 @@ impl DataStore for Data {
 	}
 
 	/// Retrieves the pdf Json from a file
-	fn get_pdf_json(&self, weekday: Weekdays) -> Result<String, Box<dyn Error>> {
-		let path = format!("{}/{}/{}.json", self.data_directory, PDF_JSON_DIR_NAME, weekday);
-		log::trace!("Get weekday pdf json path: `{}`", path);
+	fn get_pdf_json(&self, date: NaiveDate) -> Result<String, Box<dyn Error>> {
+		let path = self.pdf_json_path(date);
+		log::trace!("Get pdf json path: `{}`", path);
 		let path = Path::new(path.as_str());
 		log::trace!("Path exists: {}", path.exists());
 
@@ -72,10 +223,10 @@ impl DataStore for Data {
 		Ok(content)
 	}
 
-	/// Checks the days pdf json and if it is too old, deletes it.
+	/// Deletes the pdf json of the date.
 	/// Returns Ok if the file does not exist.
-	fn delete_pdf_json(&self, weekday: Weekdays) -> Result<(), Box<dyn Error>> {
-		let path = format!("{}/{}/{}.json", self.data_directory, PDF_JSON_DIR_NAME, weekday);
+	fn delete_pdf_json(&self, date: NaiveDate) -> Result<(), Box<dyn Error>> {
+		let path = self.pdf_json_path(date);
 		let path = Path::new(path.as_str());
 		if !path.exists() {
 			return Ok(());
@@ -84,6 +235,15 @@ impl DataStore for Data {
 		Ok(())
 	}
 
+	fn get_pdf_json_dates(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
+		let mut dates = Vec::new();
+		for entry in std::fs::read_dir(format!("{}/{}", self.data_directory, PDF_JSON_DIR_NAME))? {
+			dates.extend(Self::date_of_pdf_json(&entry?.path()));
+		}
+		dates.sort();
+		Ok(dates)
+	}
+
 	/// Stores the class whitelist or updates it with new data.
 	fn update_class_whitelist(&self, classes: &HashSet<String>) -> Result<(), Box<dyn Error + '_>> {
 		let mut class_whitelist_file = self.whitelist_file.lock()?;
@@ -123,6 +283,7 @@ impl DataStore for Data {
 			.read(true)
 			.write(true)
 			.create(true)
+			.truncate(false)
 			.open(classes_and_users_path)?;
 		let classes_and_users: HashMap<String, HashSet<u64>> = serde_json::from_reader(classes_and_users_file)?;
 		Ok(classes_and_users)
@@ -139,18 +300,183 @@ impl DataStore for Data {
 		classes_and_users_save_file.write_all(json.as_bytes())?;
 		Ok(())
 	}
+
+	/// The PDF and schedule are only written if this PDF wasn't archived before.
+	/// Nothing is added if the PDF is already the latest revision of the date, e.g. when a failed notification is tried again.
+	fn archive_revision(&self, revision: &ArchivedRevision, pdf: &[u8], schedule_json: &str) -> Result<(), Box<dyn Error>> {
+		let _lock = self.archive_lock.lock().unwrap();
+
+		let pdf_path = self.archive_path(ARCHIVE_PDF_DIR_NAME, &format!("{}.pdf", revision.sha256));
+		if !Path::new(&pdf_path).exists() {
+			std::fs::write(&pdf_path, pdf)?;
+			std::fs::write(self.archive_path(ARCHIVE_SCHEDULE_DIR_NAME, &format!("{}.json", revision.sha256)), schedule_json)?;
+		}
+
+		let revisions_path = self.revisions_path(revision.date);
+		let mut revisions = read_json_list::<ArchivedRevision>(&revisions_path)?;
+		if revisions.last().is_some_and(|last| last.sha256 == revision.sha256) {
+			return Ok(());
+		}
+		revisions.push(revision.clone());
+		std::fs::write(&revisions_path, serde_json::to_string_pretty(&revisions)?)?;
+
+		Ok(())
+	}
+
+	fn get_revisions(&self, date: i64) -> Result<Vec<ArchivedRevision>, Box<dyn Error>> {
+		read_json_list::<ArchivedRevision>(&self.revisions_path(date))
+	}
+
+	fn get_revision_at(&self, date: i64, time: i64) -> Result<Option<ArchivedRevision>, Box<dyn Error>> {
+		Ok(self.get_revisions(date)?
+			.into_iter()
+			.take_while(|revision| revision.fetched_at <= time)
+			.last())
+	}
+
+	fn get_archived_pdf(&self, sha256: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+		Ok(std::fs::read(self.archive_path(ARCHIVE_PDF_DIR_NAME, &format!("{}.pdf", sha256)))?)
+	}
+
+	fn get_archived_schedule_json(&self, sha256: &str) -> Result<String, Box<dyn Error>> {
+		Ok(std::fs::read_to_string(self.archive_path(ARCHIVE_SCHEDULE_DIR_NAME, &format!("{}.json", sha256)))?)
+	}
+
+	/// Removes the revisions of the dates before `oldest_date`, then the PDFs and schedules no revision refers to anymore
+	fn prune_archive(&self, oldest_date: i64) -> Result<usize, Box<dyn Error>> {
+		let _lock = self.archive_lock.lock().unwrap();
+		let mut referenced = HashSet::new();
+		let mut removed_dates = 0;
+
+		for entry in std::fs::read_dir(format!("{}/{}/{}", self.data_directory, ARCHIVE_DIR_NAME, ARCHIVE_REVISION_DIR_NAME))? {
+			let path = entry?.path();
+			let revisions = read_json_list::<ArchivedRevision>(&path.to_string_lossy())?;
+
+			if revisions.first().is_some_and(|revision| revision.date < oldest_date) {
+				std::fs::remove_file(&path)?;
+				removed_dates += 1;
+			} else {
+				referenced.extend(revisions.into_iter().map(|revision| revision.sha256));
+			}
+		}
+
+		for dir in &[ARCHIVE_PDF_DIR_NAME, ARCHIVE_SCHEDULE_DIR_NAME] {
+			for entry in std::fs::read_dir(format!("{}/{}/{}", self.data_directory, ARCHIVE_DIR_NAME, dir))? {
+				let path = entry?.path();
+				let sha256 = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+				if !referenced.contains(&sha256) {
+					std::fs::remove_file(&path)?;
+				}
+			}
+		}
+
+		Ok(removed_dates)
+	}
+
+	fn log_notification(&self, notification: &SentNotification) -> Result<(), Box<dyn Error>> {
+		let _lock = self.notification_log_lock.lock().unwrap();
+
+		let path = self.notification_log_path(notification.date);
+		let mut notifications = read_json_list::<SentNotification>(&path)?;
+		notifications.push(notification.clone());
+		std::fs::write(&path, serde_json::to_string_pretty(&notifications)?)?;
+
+		Ok(())
+	}
+
+	fn get_last_notification(&self, user_id: u64, date: i64, class: &str) -> Result<Option<SentNotification>, Box<dyn Error>> {
+		Ok(read_json_list::<SentNotification>(&self.notification_log_path(date))?
+			.into_iter()
+			.rev()
+			.find(|notification| notification.user_id == user_id && notification.class == class))
+	}
+
+	fn prune_notification_log(&self, oldest_date: i64) -> Result<usize, Box<dyn Error>> {
+		let _lock = self.notification_log_lock.lock().unwrap();
+		let mut removed_dates = 0;
+
+		for entry in std::fs::read_dir(format!("{}/{}", self.data_directory, NOTIFICATION_LOG_DIR_NAME))? {
+			let path = entry?.path();
+			let notifications = read_json_list::<SentNotification>(&path.to_string_lossy())?;
+
+			if notifications.first().is_some_and(|notification| notification.date < oldest_date) {
+				std::fs::remove_file(&path)?;
+				removed_dates += 1;
+			}
+		}
+
+		Ok(removed_dates)
+	}
+
+	fn get_outbox(&self) -> Result<Vec<OutboxMessage>, Box<dyn Error>> {
+		read_json_list(&format!("{}/{}", self.data_directory, OUTBOX_FILE_NAME))
+	}
+
+	fn store_outbox(&self, outbox: &[OutboxMessage]) -> Result<(), Box<dyn Error>> {
+		std::fs::write(format!("{}/{}", self.data_directory, OUTBOX_FILE_NAME), serde_json::to_string_pretty(outbox)?)?;
+		Ok(())
+	}
+
+	fn get_pending_revisions(&self) -> Result<Vec<PendingRevision>, Box<dyn Error>> {
+		read_json_list(&format!("{}/{}", self.data_directory, PENDING_REVISIONS_FILE_NAME))
+	}
+
+	/// Replaces the pending revision of the same date
+	fn set_pending_revision(&self, revision: &PendingRevision) -> Result<(), Box<dyn Error>> {
+		let _lock = self.pending_lock.lock().unwrap();
+
+		let mut pending = self.get_pending_revisions()?;
+		let mut revision = revision.clone();
+		// The same PDF is fetched again after a restart, it has been waiting since it was fetched first
+		if let Some(waiting) = pending.iter().find(|pending| pending.revision.date == revision.revision.date && pending.revision.sha256 == revision.revision.sha256) {
+			revision.revision.fetched_at = waiting.revision.fetched_at;
+		}
+		pending.retain(|pending| pending.revision.date != revision.revision.date);
+		pending.push(revision);
+		std::fs::write(format!("{}/{}", self.data_directory, PENDING_REVISIONS_FILE_NAME), serde_json::to_string_pretty(&pending)?)?;
+
+		Ok(())
+	}
+
+	fn remove_pending_revision(&self, date: i64) -> Result<(), Box<dyn Error>> {
+		let _lock = self.pending_lock.lock().unwrap();
+
+		let mut pending = self.get_pending_revisions()?;
+		if pending.iter().any(|pending| pending.revision.date == date) {
+			pending.retain(|pending| pending.revision.date != date);
+			std::fs::write(format!("{}/{}", self.data_directory, PENDING_REVISIONS_FILE_NAME), serde_json::to_string_pretty(&pending)?)?;
+		}
+
+		Ok(())
+	}
+
+	fn get_dm_failures(&self) -> Result<HashMap<u64, u32>, Box<dyn Error>> {
+		match std::fs::read_to_string(format!("{}/{}", self.data_directory, DM_FAILURES_FILE_NAME)) {
+			Ok(content) => Ok(serde_json::from_str(&content)?),
+			Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+			Err(why) => Err(why.into()),
+		}
+	}
+
+	fn store_dm_failures(&self, failures: &HashMap<u64, u32>) -> Result<(), Box<dyn Error>> {
+		std::fs::write(format!("{}/{}", self.data_directory, DM_FAILURES_FILE_NAME), serde_json::to_string_pretty(failures)?)?;
+		Ok(())
+	}
 }
 
 #[allow(clippy::module_name_repetitions)]
 pub trait DataStore {
-	/// Stores the pdf json.
-	fn store_pdf_json(&self, weekday: Weekdays, pdf_json: &str) -> Result<(), Box<dyn Error>>;
+	/// Stores the pdf json of the date the plan is for.
+	fn store_pdf_json(&self, date: NaiveDate, pdf_json: &str) -> Result<(), Box<dyn Error>>;
 
-	/// Retrieves a pdf json from the datastore.
-	fn get_pdf_json(&self, weekday: Weekdays) -> Result<String, Box<dyn Error>>;
+	/// Retrieves the pdf json of the date from the datastore.
+	fn get_pdf_json(&self, date: NaiveDate) -> Result<String, Box<dyn Error>>;
 
-	/// Checks the days pdf json and if it is too old, deletes it.
-	fn delete_pdf_json(&self, weekday: Weekdays) -> Result<(), Box<dyn Error>>;
+	/// Deletes the pdf json of the date.
+	fn delete_pdf_json(&self, date: NaiveDate) -> Result<(), Box<dyn Error>>;
+
+	/// Retrieves the dates a pdf json is stored for, sorted.
+	fn get_pdf_json_dates(&self) -> Result<Vec<NaiveDate>, Box<dyn Error>>;
 
 	/// Stores the class whitelist or updates it with new data.
 	fn update_class_whitelist(&self, classes: &HashSet<String>) -> Result<(), Box<dyn Error + '_>>;
@@ -163,6 +489,57 @@ pub trait DataStore {
 
 	/// Stores the classes and its subscribers.
 	fn store_classes_and_users(&self, classes_and_users: &HashMap<String, HashSet<u64>>) -> Result<(), Box<dyn Error>>;
+
+	/// Archives a revision of the plan of a date with its PDF and parsed schedule.
+	fn archive_revision(&self, revision: &ArchivedRevision, pdf: &[u8], schedule_json: &str) -> Result<(), Box<dyn Error>>;
+
+	/// Retrieves the archived revisions of the date, the oldest first.
+	fn get_revisions(&self, date: i64) -> Result<Vec<ArchivedRevision>, Box<dyn Error>>;
+
+	/// Retrieves the revision of the date that was the latest one at the given time.
+	fn get_revision_at(&self, date: i64, time: i64) -> Result<Option<ArchivedRevision>, Box<dyn Error>>;
+
+	/// Retrieves an archived PDF by its SHA-256.
+	fn get_archived_pdf(&self, sha256: &str) -> Result<Vec<u8>, Box<dyn Error>>;
+
+	/// Retrieves the schedule parsed from an archived PDF by the SHA-256 of the PDF.
+	fn get_archived_schedule_json(&self, sha256: &str) -> Result<String, Box<dyn Error>>;
+
+	/// Removes the revisions of the dates before `oldest_date` and everything only they referred to.
+	/// Returns the number of removed dates.
+	fn prune_archive(&self, oldest_date: i64) -> Result<usize, Box<dyn Error>>;
+
+	/// Logs a notification that was sent to a user.
+	fn log_notification(&self, notification: &SentNotification) -> Result<(), Box<dyn Error>>;
+
+	/// Retrieves the last notification the user got about the class on the date.
+	fn get_last_notification(&self, user_id: u64, date: i64, class: &str) -> Result<Option<SentNotification>, Box<dyn Error>>;
+
+	/// Removes the logged notifications about the dates before `oldest_date`.
+	/// Returns the number of removed dates.
+	fn prune_notification_log(&self, oldest_date: i64) -> Result<usize, Box<dyn Error>>;
+
+	/// Retrieves the DMs that weren't sent yet.
+	fn get_outbox(&self) -> Result<Vec<OutboxMessage>, Box<dyn Error>>;
+
+	/// Stores the DMs that weren't sent yet.
+	fn store_outbox(&self, outbox: &[OutboxMessage]) -> Result<(), Box<dyn Error>>;
+
+	/// Retrieves the revisions that are waiting for their plan to stop changing.
+	fn get_pending_revisions(&self) -> Result<Vec<PendingRevision>, Box<dyn Error>>;
+
+	/// Marks the revision as the one the users are notified about once its plan stops changing.
+	/// It replaces the pending revision of the same date, if that one has the same PDF it keeps waiting since it was fetched.
+	fn set_pending_revision(&self, revision: &PendingRevision) -> Result<(), Box<dyn Error>>;
+
+	/// Removes the pending revision of the date, if there is one.
+	fn remove_pending_revision(&self, date: i64) -> Result<(), Box<dyn Error>>;
+
+	/// Retrieves how many DMs in a row couldn't be delivered to each user.
+	fn get_dm_failures(&self) -> Result<HashMap<u64, u32>, Box<dyn Error>>;
+
+	/// Stores how many DMs in a row couldn't be delivered to each user.
+	fn store_dm_failures(&self, failures: &HashMap<u64, u32>) -> Result<(), Box<dyn Error>>;
 }
 
 #[cfg(test)]
@@ -174,13 +551,37 @@ pub mod tests {
 	#[test]
 	fn test_store_and_retrieve_pdf_json() {
 		let data = get_temp_data();
-		let day = Weekdays::Monday;
+		let monday = NaiveDate::from_ymd(2021, 11, 15);
+		let next_monday = NaiveDate::from_ymd(2021, 11, 22);
 
 		let json = "{ test: \"this is a test\" }".to_owned();
 
-		data.store_pdf_json(day, json.as_str()).unwrap();
+		data.store_pdf_json(next_monday, "{}").unwrap();
+		data.store_pdf_json(monday, json.as_str()).unwrap();
 
-		assert_eq!(json, data.get_pdf_json(day).unwrap())
+		assert_eq!(json, data.get_pdf_json(monday).unwrap());
+		assert_eq!(data.get_pdf_json(next_monday).unwrap(), "{}");
+		assert_eq!(data.get_pdf_json_dates().unwrap(), vec![monday, next_monday]);
+	}
+
+	#[test]
+	fn test_migrate_weekday_pdf_jsons() {
+		// The plans as they were stored by weekday, the one for Thursday is for 28.05.2020
+		let data_directory = format!("/tmp/test-{}", get_random_name());
+		let pdf_json_dir = format!("{}/{}", data_directory, PDF_JSON_DIR_NAME);
+		std::fs::create_dir_all(&pdf_json_dir).unwrap();
+		let thursday = std::fs::read_to_string("./legacy_data/pdf_jsons/Thursday.json").unwrap();
+		std::fs::write(format!("{}/Thursday.json", pdf_json_dir), &thursday).unwrap();
+		// The plan of the Monday before
+		std::fs::write(format!("{}/Monday.json", pdf_json_dir), thursday.replace("1590624000000", "1589760000000")).unwrap();
+		std::fs::write(format!("{}/Friday.json", pdf_json_dir), "{}").unwrap();
+
+		let data = Data::new(data_directory).unwrap();
+		data.migrate_weekday_pdf_jsons(NaiveDate::from_ymd(2020, 5, 25)).unwrap();
+
+		assert_eq!(data.get_pdf_json_dates().unwrap(), vec![NaiveDate::from_ymd(2020, 5, 28)]);
+		assert_eq!(data.get_pdf_json(NaiveDate::from_ymd(2020, 5, 28)).unwrap(), thursday);
+		assert_eq!(std::fs::read_dir(&pdf_json_dir).unwrap().count(), 1);
 	}
 
 	#[test]
@@ -213,7 +614,7 @@ pub mod tests {
 	fn delete_pdf_json() {
 		let data = get_temp_data();
 		let json = "{ test: \"this is a test\" }".to_owned();
-		let day = Weekdays::Friday;
+		let day = NaiveDate::from_ymd(2021, 11, 19);
 		data.store_pdf_json(day, json.as_str()).unwrap();
 
 		data.get_pdf_json(day).unwrap(); //sanity check
@@ -223,6 +624,140 @@ pub mod tests {
 		assert!(data.get_pdf_json(day).is_err());
 	}
 
+	fn revision(sha256: &str, fetched_at: i64, date: i64) -> ArchivedRevision {
+		ArchivedRevision {
+			sha256: sha256.to_owned(),
+			fetched_at,
+			date,
+		}
+	}
+
+	#[test]
+	fn test_archive_revisions() {
+		let data = get_temp_data();
+		let day = 1_582_070_400_000; // 19.02.2020
+		let next_day = day + 86_400_000;
+
+		data.archive_revision(&revision("aaa", 100, day), b"first", "{}").unwrap();
+		// Tried again after a failed notification
+		data.archive_revision(&revision("aaa", 200, day), b"first", "{}").unwrap();
+		data.archive_revision(&revision("bbb", 300, day), b"second", "{ \"entries\": {} }").unwrap();
+		// Back to the first revision
+		data.archive_revision(&revision("aaa", 400, day), b"first", "{}").unwrap();
+		data.archive_revision(&revision("aaa", 500, next_day), b"first", "{}").unwrap();
+
+		assert_eq!(data.get_revisions(day).unwrap(), vec![
+			revision("aaa", 100, day),
+			revision("bbb", 300, day),
+			revision("aaa", 400, day),
+		]);
+		assert_eq!(data.get_revision_at(day, 350).unwrap(), Some(revision("bbb", 300, day)));
+		assert_eq!(data.get_revision_at(day, 50).unwrap(), None);
+		assert_eq!(data.get_archived_pdf("bbb").unwrap(), b"second");
+		assert_eq!(data.get_archived_schedule_json("bbb").unwrap(), "{ \"entries\": {} }");
+		assert!(data.get_revisions(day - 86_400_000).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_prune_archive() {
+		let data = get_temp_data();
+		let day = 1_582_070_400_000;
+		let next_day = day + 86_400_000;
+
+		data.archive_revision(&revision("old", 100, day), b"old", "{}").unwrap();
+		data.archive_revision(&revision("shared", 200, day), b"shared", "{}").unwrap();
+		data.archive_revision(&revision("shared", 300, next_day), b"shared", "{}").unwrap();
+
+		assert_eq!(data.prune_archive(next_day).unwrap(), 1);
+
+		assert!(data.get_revisions(day).unwrap().is_empty());
+		assert!(data.get_archived_pdf("old").is_err());
+		assert!(data.get_archived_schedule_json("old").is_err());
+		assert_eq!(data.get_archived_pdf("shared").unwrap(), b"shared");
+		assert_eq!(data.get_revisions(next_day).unwrap().len(), 1);
+	}
+
+	fn notification(user_id: u64, date: i64, class: &str, content_hash: &str) -> SentNotification {
+		SentNotification {
+			user_id,
+			date,
+			class: class.to_owned(),
+			content_hash: content_hash.to_owned(),
+			sent_at: 0,
+		}
+	}
+
+	#[test]
+	fn test_notification_log() {
+		let data = get_temp_data();
+		let day = 1_582_070_400_000; // 19.02.2020
+		let next_day = day + 86_400_000;
+
+		data.log_notification(&notification(1, day, "TSE191", "aaa")).unwrap();
+		data.log_notification(&notification(2, day, "TSE191", "aaa")).unwrap();
+		data.log_notification(&notification(1, day, "TSE191", "bbb")).unwrap();
+		data.log_notification(&notification(1, next_day, "TSE191", "ccc")).unwrap();
+
+		assert_eq!(data.get_last_notification(1, day, "TSE191").unwrap(), Some(notification(1, day, "TSE191", "bbb")));
+		assert_eq!(data.get_last_notification(2, day, "TSE191").unwrap(), Some(notification(2, day, "TSE191", "aaa")));
+		assert_eq!(data.get_last_notification(1, day, "MB191").unwrap(), None);
+
+		assert_eq!(data.prune_notification_log(next_day).unwrap(), 1);
+		assert_eq!(data.get_last_notification(1, day, "TSE191").unwrap(), None);
+		assert_eq!(data.get_last_notification(1, next_day, "TSE191").unwrap(), Some(notification(1, next_day, "TSE191", "ccc")));
+	}
+
+	#[test]
+	fn test_pending_revisions() {
+		let data = get_temp_data();
+		let day = 1_582_070_400_000;
+		let next_day = day + 86_400_000;
+
+		let pending = |sha256: &str, fetched_at: i64, date: i64, schedule_json: &str| PendingRevision {
+			revision: revision(sha256, fetched_at, date),
+			schedule_json: Some(schedule_json.to_owned()),
+		};
+
+		data.set_pending_revision(&pending("aaa", 100, day, "{}")).unwrap();
+		data.set_pending_revision(&pending("bbb", 200, next_day, "{}")).unwrap();
+		data.set_pending_revision(&pending("ccc", 300, day, "{}")).unwrap();
+		// The same PDF parsed again, e.g. by a newer version after a restart
+		data.set_pending_revision(&pending("ccc", 400, day, "{ \"parsed\": \"again\" }")).unwrap();
+
+		assert_eq!(data.get_pending_revisions().unwrap(), vec![pending("bbb", 200, next_day, "{}"), pending("ccc", 300, day, "{ \"parsed\": \"again\" }")]);
+
+		data.remove_pending_revision(next_day).unwrap();
+		data.remove_pending_revision(next_day).unwrap();
+		assert_eq!(data.get_pending_revisions().unwrap(), vec![pending("ccc", 300, day, "{ \"parsed\": \"again\" }")]);
+
+		// Pending from before the schedule was kept with the revision
+		std::fs::write(format!("{}/{}", data.data_directory, PENDING_REVISIONS_FILE_NAME), r#"[{ "sha256": "ddd", "fetched_at": 500, "date": 1582070400000 }]"#).unwrap();
+		assert_eq!(data.get_pending_revisions().unwrap(), vec![PendingRevision { revision: revision("ddd", 500, day), schedule_json: None }]);
+	}
+
+	#[test]
+	fn test_outbox_and_dm_failures() {
+		let data = get_temp_data();
+		let message = OutboxMessage {
+			user_id: 1,
+			date: 1_582_070_400_000,
+			content: "There are changes".to_owned(),
+			notifications: vec![notification(1, 1_582_070_400_000, "TSE191", "aaa")],
+			attempts: 2,
+			next_attempt_at: 100,
+			sent_parts: 1,
+		};
+
+		assert!(data.get_outbox().unwrap().is_empty());
+		data.store_outbox(std::slice::from_ref(&message)).unwrap();
+		assert_eq!(data.get_outbox().unwrap(), vec![message]);
+
+		assert!(data.get_dm_failures().unwrap().is_empty());
+		let failures = vec![(1, 2), (191594115907977225, 1)].into_iter().collect::<HashMap<u64, u32>>();
+		data.store_dm_failures(&failures).unwrap();
+		assert_eq!(data.get_dm_failures().unwrap(), failures);
+	}
+
 	/// Gets a `Data` struct linked to a temporary directory in /tmp.
 	/// The data directory for the test is also identifiable by the name 'test-#random-name'.
 	/// The random name/directory gets printed for debugging.
@@ -0,0 +1,128 @@
+use std::convert::TryFrom;
+
+use chrono::{Duration, NaiveTime};
+use chrono_tz::Tz;
+
+use crate::config::Notifications;
+use crate::schedule_diff::ScheduleDiff;
+use crate::school_time;
+use crate::substitution_schedule::SubstitutionSchedule;
+
+/// Holds back the notifications about a plan until it stops changing, the staff often uploads a few revisions in a row
+#[derive(Debug, Clone, Copy)]
+pub struct Debounce {
+	window: Duration,
+	urgent_within: Duration,
+	timezone: Tz,
+}
+
+impl Debounce {
+	/// `None` if the users are notified right away
+	pub fn from_config(notifications: &Notifications, timezone: Tz) -> Option<Self> {
+		if notifications.debounce_minutes == 0 {
+			return None;
+		}
+
+		let minutes = |minutes: u64| Duration::minutes(i64::try_from(minutes).unwrap_or(i64::MAX).min(i64::MAX / 60_000));
+		Some(Self {
+			window: minutes(notifications.debounce_minutes),
+			urgent_within: minutes(notifications.urgent_minutes),
+			timezone,
+		})
+	}
+
+	/// Whether the plan stayed the same long enough since the revision fetched at `changed_at`, both in milliseconds
+	pub fn is_settled(&self, changed_at: i64, now: i64) -> bool {
+		now - changed_at >= self.window.num_milliseconds()
+	}
+
+	/// Whether a changed block starts so soon that the users have to know right away.
+	/// Without an old plan every block with a substitution counts as changed.
+	pub fn is_urgent(&self, old: Option<&SubstitutionSchedule>, diff: &ScheduleDiff, new: &SubstitutionSchedule, now: i64) -> bool {
+		let changed_blocks = match old {
+			Some(_) => diff.changed_blocks(),
+			None => new.get_classes()
+				.iter()
+				.filter_map(|class| new.get_substitutions(class))
+				.flat_map(|substitutions| substitutions.iter().map(|(block, _)| block))
+				.collect(),
+		};
+
+		changed_blocks.into_iter()
+			.filter_map(|block| new.get_block_times().get(&block))
+			.filter_map(|time| NaiveTime::parse_from_str(&time.start, "%H:%M").ok())
+			.map(|start| school_time::local_to_millis(new.get_date().and_time(start), self.timezone))
+			.any(|start| start - now <= self.urgent_within.num_milliseconds())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::NaiveDate;
+
+	use super::*;
+
+	fn schedule(rows: &[&[&str]]) -> SubstitutionSchedule {
+		let table = rows.iter()
+			.map(|row| row.iter().map(|cell| cell.to_string()).collect())
+			.collect();
+
+		// 18.02.2020
+		SubstitutionSchedule::from_table(&vec![table], 1_581_984_000_000).unwrap()
+	}
+
+	fn debounce() -> Debounce {
+		Debounce::from_config(&Notifications {
+			debounce_minutes: 10,
+			urgent_minutes: 60,
+			..Notifications::default()
+		}, school_time::DEFAULT_TIMEZONE).unwrap()
+	}
+
+	/// 18.02.2020 in Berlin
+	fn at(hour: u32, minute: u32) -> i64 {
+		school_time::local_to_millis(NaiveDate::from_ymd(2020, 2, 18).and_hms(hour, minute, 0), school_time::DEFAULT_TIMEZONE)
+	}
+
+	#[test]
+	fn test_disabled_without_window() {
+		assert!(Debounce::from_config(&Notifications::default(), school_time::DEFAULT_TIMEZONE).is_none());
+	}
+
+	#[test]
+	fn test_is_settled() {
+		let debounce = debounce();
+
+		assert!(!debounce.is_settled(at(7, 0), at(7, 9)));
+		assert!(debounce.is_settled(at(7, 0), at(7, 10)));
+	}
+
+	#[test]
+	fn test_is_urgent() {
+		let debounce = debounce();
+		let old = schedule(&[
+			&["", "TSE191"],
+			&["1: 07:15", ""],
+			&["- 08:00", ""],
+			&["2: 09:50", ""],
+			&["- 11:20", ""],
+		]);
+		let new = schedule(&[
+			&["", "TSE191"],
+			&["1: 07:15", ""],
+			&["- 08:00", ""],
+			&["2: 09:50", "----------"],
+			&["- 11:20", ""],
+		]);
+		let diff = ScheduleDiff::between(Some(&old), &new);
+
+		assert!(!debounce.is_urgent(Some(&old), &diff, &new, at(8, 49)));
+		assert!(debounce.is_urgent(Some(&old), &diff, &new, at(8, 50)));
+		// The first block starts soon, but it didn't change
+		assert!(!debounce.is_urgent(Some(&old), &diff, &new, at(7, 0)));
+		assert!(!debounce.is_urgent(Some(&new), &ScheduleDiff::between(Some(&new), &new), &new, at(9, 0)));
+		// The first plan of the day
+		assert!(debounce.is_urgent(None, &ScheduleDiff::between(None, &new), &new, at(9, 0)));
+		assert!(!debounce.is_urgent(None, &ScheduleDiff::between(None, &old), &old, at(9, 0)));
+	}
+}
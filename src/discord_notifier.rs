@@ -1,6 +1,12 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
 use log::error;
 use prettytable::{Cell, Row, Table};
 use prettytable::format::consts::FORMAT_BOX_CHARS;
@@ -10,17 +16,30 @@ use serenity::{
 	prelude::*,
 };
 use serenity::client::bridge::gateway::{GatewayIntents, ShardManager};
+use reqwest::StatusCode;
 use serenity::http::Http;
+use serenity::http::error::Error as HttpError;
 use serenity::model::prelude::UserId;
 
 use crate::classes_and_users::ClassesAndUsers;
 use crate::commands::{after, before, dispatch_error, Handler, normal_message, unknown_command};
 use crate::commands::*;
-use crate::config::Config;
-use crate::SOURCE_URLS;
+use crate::config::{Config, Notifications};
+use crate::data::{Data, DataStore, OutboxMessage, SentNotification};
+use crate::schedule_diff::{ClassTransition, ScheduleDiff};
+use crate::schedule_metadata::ScheduleMetadata;
+use crate::schedule_source::ScheduleSourceContainer;
 use crate::substitution_pdf_getter::Weekdays;
-use crate::substitution_schedule::{Substitutions, SubstitutionSchedule};
+use crate::substitution_schedule::{BlockTime, Substitution, Substitutions, SubstitutionSchedule};
+use crate::util;
 
+/// Discord doesn't send messages with more characters
+const MAX_MESSAGE_LENGTH: usize = 2000;
+/// Longer lines are cut, leaving room for opening and closing a code block around them
+const MAX_LINE_LENGTH: usize = 1900;
+const CODE_BLOCK_FENCE: &str = "```";
+
+#[allow(dead_code)]
 pub trait Notifier {
 	fn notify_users_for_class(&self, class: &str);
 
@@ -33,6 +52,8 @@ pub trait Notifier {
 pub struct DiscordNotifier {
 	pub http: Arc<Http>,
 	pub data: Arc<RwLock<TypeMap>>,
+	/// Held while the outbox is changed or delivered, so a DM isn't sent twice by checks running at the same time
+	outbox_lock: Mutex<()>,
 }
 
 impl DiscordNotifier {
@@ -85,65 +106,330 @@ impl DiscordNotifier {
 		Self {
 			http,
 			data,
+			outbox_lock: Mutex::new(()),
 		}
 	}
 
-	pub async fn notify_users(&self, day: Weekdays, substitutions: &SubstitutionSchedule, users_to_notify: HashSet<u64>) -> Result<(), serenity::Error> {
+	/// Puts a DM for every user with the changes in their classes and their part of the plan, with the changed blocks marked,
+	/// into the outbox and sends it.
+	/// Users who were already told exactly this about all of their classes, e.g. before a restart, are skipped.
+	pub async fn notify_users(&self, date: NaiveDate, substitutions: &SubstitutionSchedule, diff: &ScheduleDiff, users_to_notify: HashSet<u64>) -> Result<(), Box<dyn Error>> {
 		log::debug!("Notifying users on discord");
-		let data = self.data.read().await;
-		let classes_and_users = data.get::<ClassesAndUsers>().unwrap();
-
-		for user_id in users_to_notify {
-			let user = UserId::from(user_id);
-			let dm_channel = user.create_dm_channel(&self.http).await?;
-			let mut user_class_substitutions = HashMap::new();
+		let (datastore, messages) = {
+			let data = self.data.read().await;
+			let classes_and_users = data.get::<ClassesAndUsers>().unwrap();
+			let source_location = data.get::<ScheduleSourceContainer>().unwrap().location(Weekdays::from(date.weekday()));
+			let timezone = data.get::<Config>().unwrap().general.timezone;
+			let datastore = data.get::<Data>().unwrap().clone();
+			let mut messages = Vec::new();
+
+			for user_id in users_to_notify {
+				let user_classes = classes_and_users.get_user_classes(user_id);
+				let notifications = user_classes.iter()
+					.map(|class| SentNotification {
+						user_id,
+						date: substitutions.pdf_create_date,
+						class: class.clone(),
+						content_hash: Self::class_content_hash(substitutions, class),
+						sent_at: 0,
+					})
+					.collect::<Vec<SentNotification>>();
+				if Self::already_notified(&datastore, &notifications) {
+					log::debug!("User {} was already notified about the plan for {}", user_id, date);
+					continue;
+				}
 
-			for class in classes_and_users.get_user_classes(user_id) {
-				if let Some(class_substitutions) = substitutions.get_substitutions(class.as_str()) {
-					user_class_substitutions.insert(class, class_substitutions);
+				let mut user_class_substitutions = HashMap::new();
+				for class in &user_classes {
+					if let Some(class_substitutions) = substitutions.get_substitutions(class.as_str()) {
+						user_class_substitutions.insert(class.clone(), class_substitutions);
+					}
 				}
+
+				let table = Self::table_from_substitutions(&user_class_substitutions, substitutions.get_block_times(), diff);
+				let changes = match Self::diff_text(diff, &user_classes, substitutions.get_block_times()) {
+					changes if changes.is_empty() => changes,
+					changes => format!("```diff\n{}```\n", changes),
+				};
+				let metadata = Self::metadata_text(substitutions.get_metadata(), &user_classes, timezone);
+
+				messages.push(OutboxMessage {
+					user_id,
+					date: substitutions.pdf_create_date,
+					content: format!(
+						"{}\n{}```\n{}\n```\n{}Source: {}",
+						Self::headline(diff, &user_classes, date),
+						changes,
+						table,
+						metadata,
+						source_location,
+					),
+					notifications,
+					attempts: 0,
+					next_attempt_at: 0,
+					sent_parts: 0,
+				});
 			}
 
-			let table = Self::table_from_substitutions(&user_class_substitutions);
-			dm_channel.say(
-				&self.http,
-				format!(
-					"There are changes in schedule on {}: ```\n{}\n```Source: {}",
-					day,
-					table,
-					SOURCE_URLS[day as usize],
-				),
-			).await?;
+			(datastore, messages)
+		};
+
+		let _lock = self.outbox_lock.lock().await;
+		let mut outbox = datastore.get_outbox()?;
+		for message in messages {
+			queue_message(&mut outbox, message);
 		}
+		datastore.store_outbox(&outbox)?;
 
+		self.deliver_outbox().await;
 		Ok(())
 	}
 
-	#[allow(clippy::needless_range_loop)]
-	fn table_from_substitutions(substitutions: &HashMap<String, &Substitutions>) -> Table {
-		let hour_marks = [
-			"0: 07:15\n - 08:00",
-			"1: 08:00\n - 09:30",
-			"2: 09:50\n - 11:20",
-			"3: 11:40\n - 13:10",
-			"4: 13:30\n - 15:00",
-			"5: 15:15\n - 16:45"
-		];
+	/// Sends the DMs in the outbox that are due unless they are being sent right now, e.g. to try the failed ones again
+	pub async fn retry_outbox(&self) {
+		if let Ok(_lock) = self.outbox_lock.try_lock() {
+			self.deliver_outbox().await;
+		}
+	}
+
+	/// Sends the DMs in the outbox that are due, one after another, the outbox has to be locked.
+	/// Every user is handled on their own: a failed DM is tried again later and users who can't be reached,
+	/// e.g. because they blocked the bot, are unsubscribed after a few DMs in a row.
+	async fn deliver_outbox(&self) {
+		let (datastore, config) = {
+			let data = self.data.read().await;
+			(data.get::<Data>().unwrap().clone(), data.get::<Config>().unwrap().notifications.clone())
+		};
+
+		let mut outbox = match datastore.get_outbox() {
+			Ok(outbox) => outbox,
+			Err(why) => {
+				error!("Couldn't read the outbox: {}", why);
+				return;
+			}
+		};
+		let mut failures = datastore.get_dm_failures().unwrap_or_default();
+		let now = Utc::now().timestamp_millis();
+		let mut index = 0;
+
+		while index < outbox.len() {
+			let message = &mut outbox[index];
+			if message.next_attempt_at > now {
+				index += 1;
+				continue;
+			}
+			// Sent before the outbox could be stored, e.g. the bot was stopped
+			if Self::already_notified(&datastore, &message.notifications) {
+				outbox.remove(index);
+				if let Err(why) = datastore.store_outbox(&outbox) {
+					error!("Couldn't store the outbox: {}", why);
+				}
+				continue;
+			}
+
+			let result = self.send_dm(message).await;
+			let user_id = message.user_id;
+			match result {
+				Ok(()) => {
+					failures.remove(&user_id);
+					let sent_at = Utc::now().timestamp_millis();
+					for notification in &message.notifications {
+						let notification = SentNotification {
+							sent_at,
+							..notification.clone()
+						};
+						if let Err(why) = datastore.log_notification(&notification) {
+							error!("Couldn't log the notification of user {}: {}", user_id, why);
+						}
+					}
+					outbox.remove(index);
+				}
+				Err(why) if is_rejected(&why) => {
+					error!("Discord rejected the DM to user {}, not trying again: {}", user_id, why);
+					outbox.remove(index);
+				}
+				Err(why) if is_unreachable(&why) => {
+					log::warn!("Couldn't send a DM to user {}: {}", user_id, why);
+					outbox.remove(index);
+					let count = failures.entry(user_id).or_default();
+					*count += 1;
+					if *count >= config.unsubscribe_after {
+						failures.remove(&user_id);
+						self.unsubscribe(user_id).await;
+					}
+				}
+				Err(why) => {
+					message.attempts += 1;
+					if message.attempts >= config.max_attempts {
+						error!("Giving up on the DM to user {} after {} attempts: {}", user_id, message.attempts, why);
+						outbox.remove(index);
+					} else {
+						log::warn!("Sending a DM to user {} failed, trying again later: {}", user_id, why);
+						message.next_attempt_at = now + retry_delay(&config, message.attempts);
+						index += 1;
+					}
+				}
+			}
+
+			// Stored after every DM, so a DM isn't sent again if the bot is stopped
+			if let Err(why) = datastore.store_outbox(&outbox) {
+				error!("Couldn't store the outbox: {}", why);
+			}
+			tokio::time::sleep(Duration::from_millis(config.send_delay)).await;
+		}
+
+		if let Err(why) = datastore.store_dm_failures(&failures) {
+			error!("Couldn't store the failed DMs: {}", why);
+		}
+	}
+
+	async fn send_dm(&self, message: &mut OutboxMessage) -> Result<(), serenity::Error> {
+		let dm_channel = &UserId::from(message.user_id).create_dm_channel(&self.http).await?;
+		let http = &self.http;
+		send_parts(message, move |part| async move {
+			dm_channel.say(http, part).await.map(|_| ())
+		}).await
+	}
+
+	async fn unsubscribe(&self, user_id: u64) {
+		let mut data = self.data.write().await;
+		let classes_and_users = data.get_mut::<ClassesAndUsers>().unwrap();
+
+		match classes_and_users.remove_user(user_id) {
+			Ok(classes) => log::warn!("Unsubscribed user {} from {:?}, the DMs to them keep failing", user_id, classes),
+			Err(why) => error!("Couldn't unsubscribe user {}: {}", user_id, why),
+		}
+	}
+
+	/// Whether the user already got the last notification about all of these classes, with the same content
+	fn already_notified(datastore: &Data, notifications: &[SentNotification]) -> bool {
+		notifications.iter().all(|notification| {
+			matches!(
+				datastore.get_last_notification(notification.user_id, notification.date, &notification.class),
+				Ok(Some(last)) if last.content_hash == notification.content_hash
+			)
+		})
+	}
+
+	/// The weekday and date, e.g. "Monday 15.11."
+	fn day_label(date: NaiveDate) -> String {
+		format!("{} {}", Weekdays::from(date.weekday()), date.format("%d.%m."))
+	}
+
+	/// Lists the absent classes of the user, the absent teachers, the notices and the issue time in the school timezone, one per line
+	fn metadata_text(metadata: &ScheduleMetadata, user_classes: &[String], timezone: Tz) -> String {
+		let mut text = String::new();
 
+		let absent_classes = user_classes.iter()
+			.filter(|class| metadata.is_class_absent(class))
+			.map(String::as_str)
+			.collect::<Vec<&str>>();
+		if !absent_classes.is_empty() {
+			text.push_str(&format!("Absent classes: {}\n", absent_classes.join(", ")));
+		}
+		if !metadata.absent_teachers.is_empty() {
+			text.push_str(&format!("Absent teachers: {}\n", metadata.absent_teachers.join(", ")));
+		}
+		for notice in &metadata.notices {
+			text.push_str(&format!("> {}\n", notice));
+		}
+		if let Some(issued_at) = metadata.issued_at {
+			text.push_str(&format!("Issued: {}\n", timezone.timestamp_millis(issued_at).format("%d.%m.%Y %H:%M")));
+		}
+
+		text
+	}
+
+	/// Identifies what a user is told about a class: its substitutions, whether it's absent and the notices mentioning it
+	fn class_content_hash(substitutions: &SubstitutionSchedule, class: &str) -> String {
+		let blocks = substitutions.get_substitutions(class)
+			.map(|class_substitutions| class_substitutions.iter()
+				.map(|(block, substitution)| (block, Self::one_line(substitution)))
+				.collect::<BTreeMap<u32, String>>());
+		let metadata = substitutions.get_metadata();
+		let content = serde_json::json!({
+			"blocks": blocks,
+			"absent": metadata.is_class_absent(class),
+			"notices": metadata.notices_for_class(class),
+		});
+
+		util::sha256_hex(content.to_string().as_bytes())
+	}
+
+	/// The substitution without the line breaks, e.g. "Substitution FÄN / D208"
+	fn one_line(substitution: &Substitution) -> String {
+		substitution.to_string().split_whitespace().collect::<Vec<&str>>().join(" ")
+	}
+
+	/// One line for every class of the user that changed, or a general one if only e.g. the notices did
+	fn headline(diff: &ScheduleDiff, user_classes: &[String], date: NaiveDate) -> String {
+		let lines = user_classes.iter()
+			.filter_map(|class| diff.get_transition(class).map(|transition| Self::transition_text(transition, class, date)))
+			.collect::<Vec<String>>();
+
+		if lines.is_empty() {
+			format!("There are changes in schedule on {}", Self::day_label(date))
+		} else {
+			lines.join("\n")
+		}
+	}
+
+	fn transition_text(transition: ClassTransition, class: &str, date: NaiveDate) -> String {
+		let day = Self::day_label(date);
+
+		match transition {
+			ClassTransition::FirstPublication => format!("The plan for {} is out for {}", day, class),
+			ClassTransition::NewClass => format!("{} is in the plan for {} now", class, day),
+			ClassTransition::RemovedClass => format!("{} was removed from the plan for {}", class, day),
+			ClassTransition::Emptied => format!("All substitutions of {} on {} were removed", class, day),
+			ClassTransition::Changed => format!("The substitutions of {} on {} changed", class, day),
+		}
+	}
+
+	/// The changed blocks of the classes of the user as a diff, the old substitution with a "-" and the new one with a "+"
+	fn diff_text(diff: &ScheduleDiff, user_classes: &[String], block_times: &BTreeMap<u32, BlockTime>) -> String {
+		let mut text = String::new();
+
+		for class in user_classes {
+			for (block, change) in diff.get_class_changes(class).into_iter().flatten() {
+				match block_times.get(block) {
+					Some(time) => text.push_str(&format!("  {} block {} ({} - {})\n", class, block, time.start, time.end)),
+					None => text.push_str(&format!("  {} block {}\n", class, block)),
+				}
+				if let Some(before) = change.before() {
+					text.push_str(&format!("- {}\n", Self::one_line(before)));
+				}
+				if let Some(after) = change.after() {
+					text.push_str(&format!("+ {}\n", Self::one_line(after)));
+				}
+			}
+		}
+
+		text
+	}
+
+	/// The blocks that changed according to the diff are marked with a "*"
+	fn table_from_substitutions(substitutions: &HashMap<String, &Substitutions>, block_times: &BTreeMap<u32, BlockTime>, diff: &ScheduleDiff) -> Table {
 		let first = substitutions.values()
-			.map(|s| s.first_substitution())
+			.filter_map(|s| s.first_substitution())
 			.min()
-			.unwrap_or(0); // first_substitution guarantees that there is at least 1 element
+			.or_else(|| block_times.keys().next().copied())
+			.unwrap_or(0);
 
 		let last = substitutions.values()
-			.map(|s| s.last_substitution())
+			.filter_map(|s| s.last_substitution())
 			.max()
-			.unwrap_or(5); // last_substitution guarantees that there is at least 1 element
+			.or_else(|| block_times.keys().next_back().copied())
+			.unwrap_or(first);
 
 		//FIXME replace table creation with table builder.
-		let first_column = hour_marks[first..=last].iter()
-			.map(|r| {
-				Row::new(vec![Cell::new(r)])
+		let first_column = (first..=last)
+			.map(|block| {
+				let block_mark = match block_times.get(&block) {
+					Some(time) => format!("{}: {}\n - {}", block, time.start, time.end),
+					None => block.to_string(),
+				};
+				Row::new(vec![Cell::new(&block_mark)])
 			})
 			.collect::<Vec<Row>>();
 
@@ -152,16 +438,14 @@ impl DiscordNotifier {
 		table.set_format(*FORMAT_BOX_CHARS);
 
 		for (class, substitution) in substitutions {
-			let substitution_array = substitution.as_array();
-
 			table.get_mut_row(0).unwrap().add_cell(Cell::new(class));
 
-			for i in first..=last {
-				let row = table.get_mut_row(i - first + 1).unwrap();
-				if let Some(block) = substitution_array[i] {
-					row.add_cell(Cell::new(block));
-				} else {
-					row.add_cell(Cell::new(""));
+			for block in first..=last {
+				let row = table.get_mut_row((block - first + 1) as usize).unwrap();
+				let marker = if diff.get(class, block).is_some() { "* " } else { "" };
+				match substitution.get(block) {
+					Some(substitution) => row.add_cell(Cell::new(&format!("{}{}", marker, substitution))),
+					None => row.add_cell(Cell::new(marker.trim_end())),
 				}
 			}
 		}
@@ -170,6 +454,106 @@ impl DiscordNotifier {
 	}
 }
 
+/// The user blocked the bot, doesn't share a server with it anymore or was deleted, trying again won't help
+fn is_unreachable(why: &serenity::Error) -> bool {
+	match why {
+		serenity::Error::Http(http_error) => matches!(
+			http_error.as_ref(),
+			HttpError::UnsuccessfulRequest(response) if response.status_code == StatusCode::FORBIDDEN || response.status_code == StatusCode::NOT_FOUND
+		),
+		_ => false,
+	}
+}
+
+/// Discord rejected the message itself, e.g. because it's too long, sending it again won't help
+fn is_rejected(why: &serenity::Error) -> bool {
+	match why {
+		serenity::Error::Http(http_error) => matches!(
+			http_error.as_ref(),
+			HttpError::UnsuccessfulRequest(response) if response.status_code == StatusCode::BAD_REQUEST
+		),
+		_ => false,
+	}
+}
+
+/// Puts the message into the outbox, replacing the queued one about the same date since the user only needs to know
+/// the latest state of the plan. A message that was partly sent stays until its other parts are sent as well.
+fn queue_message(outbox: &mut Vec<OutboxMessage>, message: OutboxMessage) {
+	outbox.retain(|queued| queued.sent_parts > 0 || queued.user_id != message.user_id || queued.date != message.date);
+	outbox.push(message);
+}
+
+/// Sends the parts of the message that weren't sent yet, so a failed message doesn't repeat the parts the user already got
+async fn send_parts<Fut: Future<Output=Result<(), serenity::Error>>>(message: &mut OutboxMessage, mut send: impl FnMut(String) -> Fut) -> Result<(), serenity::Error> {
+	for part in split_message(&message.content).into_iter().skip(message.sent_parts) {
+		send(part).await?;
+		message.sent_parts += 1;
+	}
+	Ok(())
+}
+
+/// Splits a message into parts that fit into a DM, at line breaks where possible.
+/// A code block that doesn't fit into one part is closed at the end of the part and opened again in the next one.
+fn split_message(content: &str) -> Vec<String> {
+	let mut parts = Vec::new();
+	let mut part = String::new();
+	let mut part_length = 0;
+	// The line that opened the code block the part ends in, e.g. "```diff"
+	let mut open_fence: Option<String> = None;
+
+	for line in content.lines().flat_map(split_line) {
+		let line_length = line.chars().count();
+		if part_length > 0 && part_length + 1 + line_length + CODE_BLOCK_FENCE.len() + 1 > MAX_MESSAGE_LENGTH {
+			if open_fence.is_some() {
+				part.push('\n');
+				part.push_str(CODE_BLOCK_FENCE);
+			}
+			parts.push(std::mem::take(&mut part));
+			part_length = 0;
+			if let Some(fence) = &open_fence {
+				part.push_str(fence);
+				part_length = fence.chars().count();
+			}
+		}
+
+		if part_length > 0 {
+			part.push('\n');
+			part_length += 1;
+		}
+		part.push_str(&line);
+		part_length += line_length;
+		if line.starts_with(CODE_BLOCK_FENCE) {
+			open_fence = match open_fence {
+				Some(_) => None,
+				None => Some(line),
+			};
+		}
+	}
+	if part_length > 0 {
+		parts.push(part);
+	}
+
+	parts
+}
+
+/// Cuts a line that is too long for a part of a message into pieces
+fn split_line(line: &str) -> Vec<String> {
+	let chars = line.chars().collect::<Vec<char>>();
+	if chars.is_empty() {
+		return vec![String::new()];
+	}
+
+	chars.chunks(MAX_LINE_LENGTH)
+		.map(|piece| piece.iter().collect())
+		.collect()
+}
+
+/// The delay in milliseconds before the next attempt, doubling with every failed attempt
+fn retry_delay(config: &Notifications, attempts: u32) -> i64 {
+	let delay = config.retry_delay.saturating_mul(2_u64.saturating_pow(attempts.saturating_sub(1)));
+	i64::try_from(delay.saturating_mul(1000)).unwrap_or(i64::MAX)
+}
+
 struct ShardManagerContainer;
 
 impl TypeMapKey for ShardManagerContainer {
@@ -179,28 +563,44 @@ impl TypeMapKey for ShardManagerContainer {
 
 #[cfg(test)]
 mod tests {
+	use chrono::NaiveDateTime;
+
+	use serenity::http::error::ErrorResponse;
+
+	use crate::school_time;
+	use crate::substitution_schedule::Substitution;
+
 	use super::*;
 
+	fn block_times() -> BTreeMap<u32, BlockTime> {
+		let times = [("07:15", "08:00"), ("08:00", "09:30"), ("09:50", "11:20"), ("11:40", "13:10"), ("13:30", "15:00"), ("15:15", "16:45")];
+
+		times.iter()
+			.enumerate()
+			.map(|(block, (start, end))| (block as u32, BlockTime { start: (*start).to_owned(), end: (*end).to_owned() }))
+			.collect()
+	}
+
 	#[test]
 	fn test_table_generation() {
 		let mut table_map = HashMap::new();
 
 		let mut first = Substitutions::new();
-		let _ = first.block_1.insert("ONE".to_owned());
-		let _ = first.block_3.insert("THREE".to_owned());
-		let _ = first.block_5.insert("FIVE".to_owned());
+		first.insert(1, Substitution::from("ONE"));
+		first.insert(3, Substitution::from("THREE"));
+		first.insert(5, Substitution::from("FIVE"));
 		table_map.insert("FIRST".to_owned(), &first);
 
 		let mut second = Substitutions::new();
-		let _ = second.block_0.insert("ZERO".to_owned());
-		let _ = second.block_1.insert("ONE".to_owned());
-		let _ = second.block_2.insert("TWO".to_owned());
-		let _ = second.block_3.insert("THREE".to_owned());
-		let _ = second.block_4.insert("FOUR".to_owned());
-		let _ = second.block_5.insert("FIVE".to_owned());
+		second.insert(0, Substitution::from("ZERO"));
+		second.insert(1, Substitution::from("ONE"));
+		second.insert(2, Substitution::from("TWO"));
+		second.insert(3, Substitution::from("THREE"));
+		second.insert(4, Substitution::from("FOUR"));
+		second.insert(5, Substitution::from("FIVE"));
 		table_map.insert("SECOND".to_owned(), &second);
 
-		let out = DiscordNotifier::table_from_substitutions(&table_map);
+		let out = DiscordNotifier::table_from_substitutions(&table_map, &block_times(), &ScheduleDiff::default());
 
 		let expected_1 = "\
 		┌──────────┬────────┬───────┐\n\
@@ -256,15 +656,15 @@ mod tests {
 		let mut table_map = HashMap::new();
 
 		let mut first = Substitutions::new();
-		let _ = first.block_1.insert("ONE".to_owned());
-		let _ = first.block_4.insert("FOUR".to_owned());
+		first.insert(1, Substitution::from("ONE"));
+		first.insert(4, Substitution::from("FOUR"));
 		table_map.insert("FIRST".to_owned(), &first);
 
 		let mut second = Substitutions::new();
-		let _ = second.block_3.insert("THREE".to_owned());
+		second.insert(3, Substitution::from("THREE"));
 		table_map.insert("SECOND".to_owned(), &second);
 
-		let out = DiscordNotifier::table_from_substitutions(&table_map);
+		let out = DiscordNotifier::table_from_substitutions(&table_map, &block_times(), &ScheduleDiff::default());
 
 		let expected_1 = "\
 		┌──────────┬────────┬───────┐\n\
@@ -302,4 +702,263 @@ mod tests {
 
 		assert!(out.to_string() == expected_1 || out.to_string() == expected_2);
 	}
-}
\ No newline at end of file
+
+	fn schedule(rows: &[&[&str]]) -> SubstitutionSchedule {
+		let table = rows.iter()
+			.map(|row| row.iter().map(|cell| cell.to_string()).collect())
+			.collect();
+
+		SubstitutionSchedule::from_table(&vec![table], 0).unwrap()
+	}
+
+	fn changed_schedules() -> (SubstitutionSchedule, SubstitutionSchedule) {
+		let old = schedule(&[
+			&["", "TSE191", "MB191"],
+			&["1: 07:15", "Vertretung\nFÄN / D208", ""],
+			&["- 08:00", "", ""],
+			&["2: 08:00", "----------", "----------"],
+			&["- 09:30", "", ""],
+		]);
+		let new = schedule(&[
+			&["", "TSE191", "MB191"],
+			&["1: 07:15", "----------", "Raumverleg.MÜL\n/ D211"],
+			&["- 08:00", "", ""],
+			&["2: 08:00", "", "----------"],
+			&["- 09:30", "", ""],
+		]);
+
+		(old, new)
+	}
+
+	#[test]
+	fn test_diff_text() {
+		let (old, new) = changed_schedules();
+		let diff = ScheduleDiff::between(Some(&old), &new);
+
+		let text = DiscordNotifier::diff_text(&diff, &["TSE191".to_owned(), "BGYM171".to_owned()], new.get_block_times());
+
+		assert_eq!(text, "  TSE191 block 1 (07:15 - 08:00)\n\
+		- Substitution FÄN / D208\n\
+		+ Cancelled\n  \
+		TSE191 block 2 (08:00 - 09:30)\n\
+		- Cancelled\n");
+		assert_eq!(DiscordNotifier::diff_text(&diff, &["BGYM171".to_owned()], new.get_block_times()), "");
+	}
+
+	#[test]
+	fn test_class_content_hash() {
+		let (old, new) = changed_schedules();
+		let rewrapped = schedule(&[
+			&["", "TSE191", "MB191"],
+			&["1: 07:15", "Vertretung FÄN\n/ D208", ""],
+			&["- 08:00", "", ""],
+			&["2: 08:00", "----------", "----------"],
+			&["- 09:30", "", ""],
+		]);
+
+		assert_eq!(DiscordNotifier::class_content_hash(&old, "TSE191"), DiscordNotifier::class_content_hash(&rewrapped, "TSE191"));
+		assert_ne!(DiscordNotifier::class_content_hash(&old, "TSE191"), DiscordNotifier::class_content_hash(&new, "TSE191"));
+		assert_ne!(DiscordNotifier::class_content_hash(&old, "TSE191"), DiscordNotifier::class_content_hash(&old, "BGYM171"));
+	}
+
+	fn http_error(status_code: StatusCode, code: isize) -> serenity::Error {
+		serenity::Error::Http(Box::new(HttpError::UnsuccessfulRequest(ErrorResponse {
+			status_code,
+			url: "https://discord.com/api/v8/channels/1/messages".parse().unwrap(),
+			error: serde_json::from_value(serde_json::json!({ "code": code, "message": "" })).unwrap(),
+		})))
+	}
+
+	#[test]
+	fn test_is_unreachable() {
+		// Cannot send messages to this user
+		assert!(is_unreachable(&http_error(StatusCode::FORBIDDEN, 50007)));
+		// Unknown user
+		assert!(is_unreachable(&http_error(StatusCode::NOT_FOUND, 10013)));
+		assert!(!is_unreachable(&http_error(StatusCode::INTERNAL_SERVER_ERROR, 0)));
+		assert!(!is_unreachable(&serenity::Error::Other("timeout")));
+	}
+
+	#[test]
+	fn test_is_rejected() {
+		// Cannot send an empty message
+		assert!(is_rejected(&http_error(StatusCode::BAD_REQUEST, 50006)));
+		assert!(!is_rejected(&http_error(StatusCode::FORBIDDEN, 50007)));
+		assert!(!is_rejected(&serenity::Error::Other("timeout")));
+	}
+
+	#[test]
+	fn test_split_message() {
+		let classes = (0..40).map(|class| format!("TSE{}", class)).collect::<Vec<String>>();
+		let schedule = |text: &str| {
+			let mut table = vec![std::iter::once(String::new()).chain(classes.iter().cloned()).collect::<Vec<String>>()];
+			for (block, (start, end)) in [("07:15", "08:00"), ("08:00", "09:30"), ("09:50", "11:20")].iter().enumerate() {
+				table.push(std::iter::once(format!("{}: {}", block + 1, start)).chain(classes.iter().map(|_| text.to_owned())).collect());
+				table.push(std::iter::once(format!("- {}", end)).chain(classes.iter().map(|_| String::new())).collect());
+			}
+			SubstitutionSchedule::from_table(&vec![table], 0).unwrap()
+		};
+		let old = schedule("Vertretung\nFÄN / D208");
+		let new = schedule("Raumverleg.MÜL\n/ D211");
+		let diff = ScheduleDiff::between(Some(&old), &new);
+		let changes = DiscordNotifier::diff_text(&diff, &classes, new.get_block_times());
+		let content = format!("There are changes\n```diff\n{}```\nSource: https://example.com", changes);
+		assert!(content.chars().count() > MAX_MESSAGE_LENGTH);
+
+		let parts = split_message(&content);
+
+		assert!(parts.len() > 1);
+		for part in &parts {
+			assert!(part.chars().count() <= MAX_MESSAGE_LENGTH);
+			assert_eq!(part.lines().filter(|line| line.starts_with(CODE_BLOCK_FENCE)).count() % 2, 0);
+		}
+		assert!(parts[0].starts_with("There are changes\n```diff\n"));
+		assert!(parts[1].starts_with("```diff\n"));
+		assert!(parts.last().unwrap().ends_with("```\nSource: https://example.com"));
+		let lines = parts.iter()
+			.flat_map(|part| part.lines())
+			.collect::<Vec<&str>>()
+			.join("\n")
+			.replace("```\n```diff\n", "");
+		assert_eq!(lines, content);
+	}
+
+	fn outbox_message(user_id: u64, content: &str, sent_parts: usize) -> OutboxMessage {
+		OutboxMessage {
+			user_id,
+			date: 1_582_070_400_000,
+			content: content.to_owned(),
+			notifications: Vec::new(),
+			attempts: 0,
+			next_attempt_at: 0,
+			sent_parts,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_send_parts_fails_after_the_first_part() {
+		let content = format!("{}\n{}", "a".repeat(MAX_LINE_LENGTH), "b".repeat(MAX_LINE_LENGTH));
+		let mut message = outbox_message(1, &content, 0);
+		let sent = std::sync::Mutex::new(Vec::new());
+
+		let result = send_parts(&mut message, |part| {
+			let result = if part.starts_with('b') {
+				Err(serenity::Error::Other("timeout"))
+			} else {
+				sent.lock().unwrap().push(part);
+				Ok(())
+			};
+			async move { result }
+		}).await;
+		assert!(result.is_err());
+		assert_eq!(message.sent_parts, 1);
+
+		// A newer message about the same date doesn't replace the partly sent one
+		let mut outbox = vec![message.clone(), outbox_message(2, "Other user", 1)];
+		queue_message(&mut outbox, outbox_message(1, "Newer", 0));
+		assert_eq!(outbox, vec![message.clone(), outbox_message(2, "Other user", 1), outbox_message(1, "Newer", 0)]);
+		queue_message(&mut outbox, outbox_message(1, "Newest", 0));
+		assert_eq!(outbox, vec![message.clone(), outbox_message(2, "Other user", 1), outbox_message(1, "Newest", 0)]);
+
+		// Trying again only sends the second part
+		send_parts(&mut message, |part| {
+			sent.lock().unwrap().push(part);
+			async { Ok(()) }
+		}).await.unwrap();
+		assert_eq!(message.sent_parts, 2);
+		assert_eq!(*sent.lock().unwrap(), vec!["a".repeat(MAX_LINE_LENGTH), "b".repeat(MAX_LINE_LENGTH)]);
+	}
+
+	#[test]
+	fn test_split_message_long_line() {
+		let content = format!("Notice:\n{}", "a".repeat(4000));
+
+		let parts = split_message(&content);
+
+		assert_eq!(parts, vec![
+			format!("Notice:\n{}", "a".repeat(MAX_LINE_LENGTH)),
+			"a".repeat(MAX_LINE_LENGTH),
+			"a".repeat(4000 - 2 * MAX_LINE_LENGTH),
+		]);
+		assert_eq!(split_message("Short\n\nmessage"), vec!["Short\n\nmessage".to_owned()]);
+	}
+
+	#[test]
+	fn test_retry_delay() {
+		let config = Notifications {
+			retry_delay: 60,
+			..Notifications::default()
+		};
+
+		assert_eq!(retry_delay(&config, 1), 60_000);
+		assert_eq!(retry_delay(&config, 3), 240_000);
+		assert_eq!(retry_delay(&config, 100), i64::MAX);
+	}
+
+	#[test]
+	fn test_headline() {
+		let (old, new) = changed_schedules();
+		let date = NaiveDate::from_ymd(2021, 11, 15);
+
+		assert_eq!(
+			DiscordNotifier::headline(&ScheduleDiff::between(Some(&old), &new), &["TSE191".to_owned(), "MB191".to_owned(), "BGYM171".to_owned()], date),
+			"The substitutions of TSE191 on Monday 15.11. changed\nThe substitutions of MB191 on Monday 15.11. changed",
+		);
+		assert_eq!(DiscordNotifier::headline(&ScheduleDiff::default(), &["TSE191".to_owned()], date), "There are changes in schedule on Monday 15.11.");
+	}
+
+	#[test]
+	fn test_transition_text() {
+		let date = NaiveDate::from_ymd(2021, 11, 15);
+		let text = |transition| DiscordNotifier::transition_text(transition, "TSE191", date);
+
+		assert_eq!(text(ClassTransition::FirstPublication), "The plan for Monday 15.11. is out for TSE191");
+		assert_eq!(text(ClassTransition::NewClass), "TSE191 is in the plan for Monday 15.11. now");
+		assert_eq!(text(ClassTransition::RemovedClass), "TSE191 was removed from the plan for Monday 15.11.");
+		assert_eq!(text(ClassTransition::Emptied), "All substitutions of TSE191 on Monday 15.11. were removed");
+		assert_eq!(text(ClassTransition::Changed), "The substitutions of TSE191 on Monday 15.11. changed");
+	}
+
+	#[test]
+	fn test_table_marks_changed_blocks() {
+		let (old, new) = changed_schedules();
+		let diff = ScheduleDiff::between(Some(&old), &new);
+		let mut table_map = HashMap::new();
+		table_map.insert("TSE191".to_owned(), new.get_substitutions("TSE191").unwrap());
+
+		let out = DiscordNotifier::table_from_substitutions(&table_map, new.get_block_times(), &diff);
+
+		assert_eq!(out.to_string(), "\
+		┌──────────┬─────────────┐\n\
+		│          │ TSE191      │\n\
+		├──────────┼─────────────┤\n\
+		│ 1: 07:15 │ * Cancelled │\n\
+		│  - 08:00 │             │\n\
+		└──────────┴─────────────┘\n");
+	}
+
+	#[test]
+	fn test_metadata_text() {
+		let metadata = ScheduleMetadata {
+			// 07:35 in Berlin
+			issued_at: Some(NaiveDateTime::parse_from_str("18.02.2020 06:35", "%d.%m.%Y %H:%M").unwrap().timestamp_millis()),
+			absent_teachers: vec!["ERE".to_owned(), "FÄN".to_owned()],
+			absent_classes: vec!["TSE191".to_owned(), "MB191".to_owned()],
+			notices: vec!["Die AzuBis der Klassen TSE 191 und MB191 sind in die Betriebe bestellt.".to_owned()],
+		};
+
+		let text = DiscordNotifier::metadata_text(&metadata, &["BGYM171".to_owned(), "TSE191".to_owned()], school_time::DEFAULT_TIMEZONE);
+
+		assert_eq!(text, "\
+		Absent classes: TSE191\n\
+		Absent teachers: ERE, FÄN\n\
+		> Die AzuBis der Klassen TSE 191 und MB191 sind in die Betriebe bestellt.\n\
+		Issued: 18.02.2020 07:35\n");
+		assert_eq!(DiscordNotifier::metadata_text(&ScheduleMetadata::default(), &[], school_time::DEFAULT_TIMEZONE), "");
+	}
+
+	#[test]
+	fn test_day_label() {
+		assert_eq!(DiscordNotifier::day_label(NaiveDate::from_ymd(2021, 11, 15)), "Monday 15.11.");
+	}
+}
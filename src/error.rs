@@ -1,37 +1,164 @@
 use std::fmt::{Display, Formatter};
 
+use chrono::NaiveDateTime;
+use reqwest::StatusCode;
+
+/// Everything that can go wrong while turning a substitution PDF into a `SubstitutionSchedule`.
+/// Rows and tables are counted from 0, the header row of a table is row 0.
 #[allow(clippy::module_name_repetitions)]
-#[derive(Debug, Clone)]
-pub struct StringError {
-	message: String,
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+	/// The PDF could not be loaded.
+	MalformedPdf(String),
+	/// The tables could not be extracted from the PDF.
+	Extractor(String),
+	/// The tabula JSON is malformed.
+	MalformedJson {
+		table: Option<usize>,
+		row: Option<usize>,
+		message: String,
+	},
+	/// The tabula CSV is malformed.
+	MalformedCsv {
+		row: Option<usize>,
+		message: String,
+	},
+	/// There is no "Datum: " on the first page of the PDF.
+	MissingDate,
+	/// The date after "Datum: " could not be parsed.
+	BadDate(String),
+	/// The weekday in the date line doesn't match the date, e.g. "Freitag, 28.5.2020".
+	WeekdayMismatch {
+		weekday: String,
+		date: String,
+	},
+	/// A page has a different date than the pages before it, the dates are timestamps in milliseconds.
+	InconsistentDate {
+		page: u32,
+		date: i64,
+		page_date: i64,
+	},
+	/// The PDF contains no tables.
+	NoTables,
+	/// The table has no header row.
+	EmptyTable {
+		table: usize,
+	},
+	/// A row has more cells than the header has classes.
+	RaggedRow {
+		table: usize,
+		row: usize,
+		columns: usize,
+		expected: usize,
+	},
+	/// A block was started but not ended with a row like "- 09:30" before the next block or the end of the table.
+	UnterminatedBlock {
+		table: usize,
+		row: usize,
+		block: u32,
+	},
 }
 
-impl StringError {
-	pub fn new(message: &str) -> Self {
-		message.into()
+impl Display for ParseError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ParseError::MalformedPdf(why) => write!(f, "PDF is empty or malformed: {}", why),
+			ParseError::Extractor(why) => write!(f, "Could not extract the tables from the PDF: {}", why),
+			ParseError::MalformedJson { table, row, message } => {
+				write!(f, "Tabula JSON malformed")?;
+				if let Some(table) = table {
+					write!(f, " in table {}", table)?;
+				}
+				if let Some(row) = row {
+					write!(f, " row {}", row)?;
+				}
+				write!(f, ": {}", message)
+			}
+			ParseError::MalformedCsv { row, message } => {
+				write!(f, "Tabula CSV malformed")?;
+				if let Some(row) = row {
+					write!(f, " in row {}", row)?;
+				}
+				write!(f, ": {}", message)
+			}
+			ParseError::MissingDate => write!(f, "Date not found in the PDF"),
+			ParseError::BadDate(date) => write!(f, "Could not parse the date '{}'", date),
+			ParseError::WeekdayMismatch { weekday, date } => write!(f, "The weekday '{}' doesn't match the date {}", weekday, date),
+			ParseError::InconsistentDate { page, date, page_date } => {
+				let format_date = |millis: &i64| NaiveDateTime::from_timestamp(millis.div_euclid(1000), 0).format("%d.%m.%Y");
+				write!(f, "Page {} is for {}, but the pages before are for {}", page, format_date(page_date), format_date(date))
+			}
+			ParseError::NoTables => write!(f, "No tables found in the PDF"),
+			ParseError::EmptyTable { table } => write!(f, "Table {} has no header", table),
+			ParseError::RaggedRow { table, row, columns, expected } => write!(
+				f,
+				"Table {} row {} has {} columns, expected at most {}",
+				table, row, columns, expected
+			),
+			ParseError::UnterminatedBlock { table, row, block } => write!(
+				f,
+				"Block {} starting in table {} row {} is never ended",
+				block, table, row
+			),
+		}
 	}
 }
 
-impl Display for StringError {
-	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		write!(f, "{}", self.message)
-	}
+impl std::error::Error for ParseError {}
+
+/// Everything that can go wrong while getting a substitution PDF.
+#[derive(Debug)]
+pub enum FetchError {
+	/// The request failed, e.g. because of a timeout or a refused connection.
+	Request(reqwest::Error),
+	/// The server answered with an error status, e.g. 401 if the credentials are wrong or 503 during maintenance.
+	Status(StatusCode),
+	/// The server answered with something that isn't a PDF, e.g. an HTML login page.
+	NotAPdf {
+		content_type: Option<String>,
+	},
+	/// Reading the PDF from a local file failed.
+	Io(std::io::Error),
 }
 
-impl From<&str> for StringError {
-	fn from(message: &str) -> Self {
-		Self {
-			message: message.to_owned(),
+impl FetchError {
+	/// Whether trying again later might help
+	pub fn is_transient(&self) -> bool {
+		match self {
+			FetchError::Request(_) => true,
+			FetchError::Status(status) => status.is_server_error()
+				|| *status == StatusCode::TOO_MANY_REQUESTS
+				|| *status == StatusCode::REQUEST_TIMEOUT,
+			FetchError::NotAPdf { .. } | FetchError::Io(_) => false,
 		}
 	}
 }
 
-impl From<String> for StringError {
-	fn from(message: String) -> Self {
-		Self {
-			message
+impl Display for FetchError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			FetchError::Request(why) => write!(f, "Request failed: {}", why),
+			FetchError::Status(status) => write!(f, "Server answered with {}", status),
+			FetchError::NotAPdf { content_type } => write!(
+				f,
+				"Server answered with {} instead of a PDF",
+				content_type.as_deref().unwrap_or("an unknown content type")
+			),
+			FetchError::Io(why) => write!(f, "Reading the PDF failed: {}", why),
 		}
 	}
 }
 
-impl std::error::Error for StringError {}
\ No newline at end of file
+impl std::error::Error for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+	fn from(why: reqwest::Error) -> Self {
+		FetchError::Request(why)
+	}
+}
+
+impl From<std::io::Error> for FetchError {
+	fn from(why: std::io::Error) -> Self {
+		FetchError::Io(why)
+	}
+}
@@ -0,0 +1,204 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+use crate::config::Holidays;
+
+/// How far ahead `upcoming_school_days` looks, the PDFs are published by weekday so a week is the most we can keep apart
+const LOOKAHEAD_LIMIT_DAYS: i64 = 7;
+
+/// A named period without school, both ends inclusive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holiday {
+	pub name: String,
+	pub from: NaiveDate,
+	pub to: NaiveDate,
+}
+
+/// The school holidays and public holidays, every other day from Monday to Friday is a school day
+#[derive(Debug, Clone, Default)]
+pub struct HolidayCalendar {
+	holidays: Vec<Holiday>,
+}
+
+impl HolidayCalendar {
+	pub fn new(holidays: Vec<Holiday>) -> Self {
+		Self {
+			holidays,
+		}
+	}
+
+	/// Loads the holidays listed in [holidays] and the ones in the iCalendar file if it's set
+	pub fn from_config(config: &Holidays) -> Result<Self, String> {
+		let mut holidays = config.periods.iter()
+			.map(|period| Holiday {
+				name: period.name.clone(),
+				from: period.from,
+				to: period.to.unwrap_or(period.from),
+			})
+			.collect::<Vec<Holiday>>();
+
+		if let Some(ics_file) = &config.ics_file {
+			let ics = std::fs::read_to_string(ics_file).map_err(|why| format!("Couldn't read the holiday calendar {}: {}", ics_file, why))?;
+			holidays.extend(parse_ics(&ics)?);
+		}
+
+		log::debug!("Loaded {} holidays", holidays.len());
+		Ok(Self::new(holidays))
+	}
+
+	pub fn holiday_on(&self, date: NaiveDate) -> Option<&Holiday> {
+		self.holidays.iter().find(|holiday| holiday.from <= date && date <= holiday.to)
+	}
+
+	pub fn is_school_day(&self, date: NaiveDate) -> bool {
+		!matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && self.holiday_on(date).is_none()
+	}
+
+	/// The date itself if it's a school day, otherwise the next school day
+	pub fn next_school_day(&self, date: NaiveDate) -> NaiveDate {
+		let mut date = date;
+		while !self.is_school_day(date) {
+			date = date.succ();
+		}
+		date
+	}
+
+	/// Up to `count` school days starting with today, within the next week
+	pub fn upcoming_school_days(&self, today: NaiveDate, count: usize) -> Vec<NaiveDate> {
+		let mut days = Vec::new();
+		let mut date = self.next_school_day(today);
+
+		while days.len() < count && date < today + Duration::days(LOOKAHEAD_LIMIT_DAYS) {
+			days.push(date);
+			date = self.next_school_day(date.succ());
+		}
+
+		days
+	}
+}
+
+/// Reads the all-day events of an iCalendar file, e.g. the holidays published by the ministry.
+/// The end of an event is exclusive, a one day event on the 3rd ends on the 4th.
+fn parse_ics(ics: &str) -> Result<Vec<Holiday>, String> {
+	let mut holidays = Vec::new();
+	let mut event: Option<(Option<String>, Option<NaiveDate>, Option<NaiveDate>)> = None;
+
+	for line in unfold_lines(ics) {
+		let (name, value) = match line.split_once(':') {
+			Some(property) => property,
+			None => continue,
+		};
+		// Drop parameters like in "DTSTART;VALUE=DATE"
+		let name = name.split(';').next().unwrap_or_default();
+
+		match (name, &mut event) {
+			("BEGIN", None) if value == "VEVENT" => event = Some((None, None, None)),
+			("END", Some((summary, Some(from), to))) if value == "VEVENT" => {
+				holidays.push(Holiday {
+					name: summary.clone().unwrap_or_default(),
+					from: *from,
+					to: to.map_or(*from, |to| to.pred().max(*from)),
+				});
+				event = None;
+			}
+			("END", Some(_)) if value == "VEVENT" => return Err("An event in the holiday calendar has no DTSTART".to_owned()),
+			("SUMMARY", Some((summary, _, _))) => *summary = Some(value.replace("\\,", ",")),
+			("DTSTART", Some((_, from, _))) => *from = Some(parse_ics_date(value)?),
+			("DTEND", Some((_, _, to))) => *to = Some(parse_ics_date(value)?),
+			_ => {}
+		}
+	}
+
+	Ok(holidays)
+}
+
+/// Long lines are folded, the continuation lines start with a space or tab
+fn unfold_lines(ics: &str) -> Vec<String> {
+	let mut lines: Vec<String> = Vec::new();
+
+	for line in ics.lines() {
+		match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+			(Some(continuation), Some(previous)) => previous.push_str(continuation),
+			_ => lines.push(line.to_owned()),
+		}
+	}
+
+	lines
+}
+
+/// Parses a date like "20201221" or a date time like "20201221T000000Z", of which only the date is used
+fn parse_ics_date(value: &str) -> Result<NaiveDate, String> {
+	value.get(..8)
+		.and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+		.ok_or_else(|| format!("Malformed date '{}' in the holiday calendar", value))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+		NaiveDate::from_ymd(year, month, day)
+	}
+
+	fn christmas() -> HolidayCalendar {
+		HolidayCalendar::new(vec![Holiday {
+			name: "Weihnachtsferien".to_owned(),
+			from: date(2020, 12, 21),
+			to: date(2021, 1, 8),
+		}])
+	}
+
+	#[test]
+	fn test_school_days() {
+		let calendar = christmas();
+
+		assert!(calendar.is_school_day(date(2020, 12, 18)));
+		assert!(!calendar.is_school_day(date(2020, 12, 19)));
+		assert!(!calendar.is_school_day(date(2020, 12, 21)));
+		assert_eq!(calendar.holiday_on(date(2021, 1, 8)).unwrap().name, "Weihnachtsferien");
+		assert_eq!(calendar.next_school_day(date(2020, 12, 19)), date(2021, 1, 11));
+		assert_eq!(calendar.next_school_day(date(2021, 1, 11)), date(2021, 1, 11));
+	}
+
+	#[test]
+	fn test_upcoming_school_days() {
+		let calendar = christmas();
+
+		assert_eq!(calendar.upcoming_school_days(date(2020, 12, 17), 5), vec![date(2020, 12, 17), date(2020, 12, 18)]);
+		assert!(calendar.upcoming_school_days(date(2020, 12, 28), 5).is_empty());
+		assert_eq!(calendar.upcoming_school_days(date(2021, 1, 9), 2), vec![date(2021, 1, 11), date(2021, 1, 12)]);
+		// Every weekday only once, since the PDFs are published by weekday
+		assert_eq!(HolidayCalendar::default().upcoming_school_days(date(2021, 1, 13), 10).len(), 5);
+	}
+
+	#[test]
+	fn test_parse_ics() {
+		let ics = "BEGIN:VCALENDAR\r\n\
+			VERSION:2.0\r\n\
+			BEGIN:VEVENT\r\n\
+			DTSTART;VALUE=DATE:20201221\r\n\
+			DTEND;VALUE=DATE:20210109\r\n\
+			SUMMARY:Weihnachtsferien Niedersa\r\n \
+			chsen\r\n\
+			END:VEVENT\r\n\
+			BEGIN:VEVENT\r\n\
+			DTSTART:20210513T000000Z\r\n\
+			SUMMARY:Christi Himmelfahrt\r\n\
+			END:VEVENT\r\n\
+			END:VCALENDAR\r\n";
+
+		assert_eq!(parse_ics(ics).unwrap(), vec![
+			Holiday {
+				name: "Weihnachtsferien Niedersachsen".to_owned(),
+				from: date(2020, 12, 21),
+				to: date(2021, 1, 8),
+			},
+			Holiday {
+				name: "Christi Himmelfahrt".to_owned(),
+				from: date(2021, 5, 13),
+				to: date(2021, 5, 13),
+			},
+		]);
+		assert!(parse_ics("BEGIN:VEVENT\nDTSTART:2021\nEND:VEVENT").is_err());
+	}
+}
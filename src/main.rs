@@ -1,28 +1,39 @@
 #![allow(clippy::non_ascii_literal)]
-#![allow(clippy::let_underscore_drop)]
+#![allow(let_underscore_drop)]
 #![allow(clippy::wildcard_imports)]
 
 use std::collections::HashSet;
-use std::io::Write;
 use std::path::Path;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
 
-use chrono::{Datelike, DateTime, Local};
+use chrono::{Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
 use log::{debug, error, info, LevelFilter, trace};
 use serenity::prelude::TypeMapKey;
 use simple_logger::SimpleLogger;
+use tokio::sync::Semaphore;
 
 use crate::classes_and_users::ClassesAndUsers;
 use crate::config::Config;
-use crate::data::{Data, DataStore};
+use crate::debounce::Debounce;
+use crate::data::{ArchivedRevision, Data, DataStore, PendingRevision};
 use crate::discord_notifier::DiscordNotifier;
-use crate::substitution_pdf_getter::{SubstitutionPDFGetter, Weekdays};
+use crate::error::ParseError;
+use crate::holiday_calendar::HolidayCalendar;
+use crate::polling_scheduler::{PollingScheduler, PollTrigger};
+use crate::schedule_diff::ScheduleDiff;
+use crate::schedule_source::{FetchedPdf, PdfFetch, ScheduleSource, ScheduleSourceContainer};
+use crate::substitution_pdf_getter::Weekdays;
 use crate::substitution_schedule::SubstitutionSchedule;
 
 mod substitution_schedule;
 mod tabula_json_parser;
+mod pdf_table_extractor;
+mod schedule_metadata;
+mod schedule_diff;
+mod debounce;
 mod substitution_pdf_getter;
+mod schedule_source;
 mod commands;
 mod config;
 mod data;
@@ -30,15 +41,15 @@ mod util;
 mod error;
 mod classes_and_users;
 mod discord_notifier;
+mod polling_scheduler;
+mod holiday_calendar;
+mod school_time;
+#[cfg(test)]
+mod snapshot_tests;
+#[cfg(test)]
+mod property_tests;
 
 const TEMP_ROOT_DIR: &str = "/tmp/school-substitution-scanner-temp-dir";
-const SOURCE_URLS: [&str; 5] = [
-	"https://buessing.schule/plaene/VertretungsplanA4_Montag.pdf",
-	"https://buessing.schule/plaene/VertretungsplanA4_Dienstag.pdf",
-	"https://buessing.schule/plaene/VertretungsplanA4_Mittwoch.pdf",
-	"https://buessing.schule/plaene/VertretungsplanA4_Donnerstag.pdf",
-	"https://buessing.schule/plaene/VertretungsplanA4_Freitag.pdf",
-];
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -54,16 +65,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let config_file = std::fs::File::open("./config.toml").expect("Error opening config file");
 	let config = Config::from_file(config_file);
 	let datastore = Arc::new(Data::new("./data".to_owned())?);
+	datastore.migrate_weekday_pdf_jsons(school_time::today(config.general.timezone))?;
 
 	if let Err(why) = datastore.update_class_whitelist(&config.general.class_whitelist) {
 		log::error!("{}", why);
 	}
 
+	let retention_days = config.archive.retention_days;
+	let log_retention_days = config.notifications.log_retention_days;
+	let timezone = config.general.timezone;
+	let debounce = Debounce::from_config(&config.notifications, timezone);
+	let calendar = Arc::new(HolidayCalendar::from_config(&config.holidays).expect("Couldn't load the holidays"));
+	let scheduler = PollingScheduler::new(config.polling.clone(), calendar.clone());
+	let poll_trigger = Arc::new(PollTrigger::default());
+	let lookahead_days = config.polling.lookahead_days;
+	// Bounds how many days are checked at the same time, they all share the client of the source
+	let check_permits = Arc::new(Semaphore::new(config.polling.max_concurrent_checks.max(1)));
+	let running_checks = Arc::new(Mutex::new(HashSet::new()));
+	let pdf_getter = schedule_source::from_config(config.source.clone());
 	let discord_notifier = Arc::from(DiscordNotifier::new(config).await);
 
 	{
 		let mut data = discord_notifier.data.write().await;
 
+		data.insert::<ScheduleSourceContainer>(pdf_getter.clone());
+		data.insert::<PollTrigger>(poll_trigger.clone());
+
 		let datastore_arc = datastore.clone();
 		data.insert::<Data>(datastore_arc);
 
@@ -71,87 +98,234 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 		data.insert::<ClassesAndUsers>(classes_and_users);
 	}
 
-	let pdf_getter = Arc::new(SubstitutionPDFGetter::default());
-
 	let mut counter: u32 = 0;
+	let mut last_prune = None;
 	info!("Starting loop");
 	loop {
 		trace!("Loop start");
 
-		let local: DateTime<Local> = Local::now();
-		let next_valid_school_weekday = Weekdays::from(local.weekday());
-		let day_after = next_valid_school_weekday.next_day();
+		let today = school_time::today(timezone);
 
-		debug!("Local day: {}; next valid school day: {}; day after that: {}", local.weekday(), next_valid_school_weekday, day_after);
+		// Prune the archive and the notification log and remove the stale plans once a day
+		if last_prune != Some(today) {
+			delete_stale_plans(&datastore, &calendar, today);
+			let oldest_date = school_time::date_to_millis(today - chrono::Duration::days(i64::from(retention_days)));
+			match datastore.prune_archive(oldest_date) {
+				Ok(removed) => debug!("Removed the archived plans of {} dates", removed),
+				Err(why) => error!("Couldn't prune the archive: {}", why),
+			}
+			let oldest_logged_date = school_time::date_to_millis(today - chrono::Duration::days(i64::from(log_retention_days)));
+			match datastore.prune_notification_log(oldest_logged_date) {
+				Ok(removed) => debug!("Removed the notification log of {} dates", removed),
+				Err(why) => error!("Couldn't prune the notification log: {}", why),
+			}
+			last_prune = Some(today);
+		}
 
+		// The source has one PDF per weekday, which is for the next date of the weekday
+		let mut days: Vec<(Weekdays, NaiveDate)> = Vec::new();
+		for date in calendar.upcoming_school_days(today, lookahead_days) {
+			let day = Weekdays::from(date.weekday());
+			if !days.iter().any(|(checked_day, _)| *checked_day == day) {
+				days.push((day, date));
+			}
+		}
+		debug!("School day: {}; checking {:?}", today.weekday(), days);
 
-		let pdf_getter_arc = pdf_getter.clone();
-		let discord_notifier_arc = discord_notifier.clone();
-		let datastore_arc = datastore.clone();
-		tokio::spawn(async move {
-			if let Err(why) = check_weekday_pdf(next_valid_school_weekday, pdf_getter_arc, discord_notifier_arc, datastore_arc).await {
-				error!("{}", why);
+		for (day, date) in days {
+			// A check of the day that is still running or waiting would notify about the same changes again
+			if !running_checks.lock().unwrap().insert(day) {
+				debug!("The last check of {} is still running", day);
+				continue;
 			}
-		});
 
-		let pdf_getter_arc = pdf_getter.clone();
+			let pdf_getter_arc = pdf_getter.clone();
+			let discord_notifier_arc = discord_notifier.clone();
+			let datastore_arc = datastore.clone();
+			let calendar_arc = calendar.clone();
+			let check_permits = check_permits.clone();
+			let running_checks = running_checks.clone();
+			tokio::spawn(async move {
+				let _permit = check_permits.acquire_owned().await;
+				if let Err(why) = check_weekday_pdf(day, date, pdf_getter_arc, discord_notifier_arc, datastore_arc, calendar_arc, timezone, debounce).await {
+					error!("Checking the PDF for {} failed: {}", day, why);
+				}
+				running_checks.lock().unwrap().remove(&day);
+			});
+		}
+
+		// The DMs that couldn't be sent before are tried again once they are due
 		let discord_notifier_arc = discord_notifier.clone();
-		let datastore_arc = datastore.clone();
 		tokio::spawn(async move {
-			if let Err(why) = check_weekday_pdf(day_after, pdf_getter_arc, discord_notifier_arc, datastore_arc).await {
-				error!("{}", why);
-			}
+			discord_notifier_arc.retry_outbox().await;
 		});
 
 		counter += 1;
 		debug!("Loop ran {} times", counter);
 		trace!("Loop end before sleep");
-		tokio::time::sleep(Duration::from_secs(20)).await;
+		let delay = scheduler.next_delay(school_time::now(timezone), rand::random());
+		debug!("Checking again in {:?}", delay);
+		tokio::select! {
+			_ = tokio::time::sleep(delay) => {}
+			_ = poll_trigger.fired() => info!("Checking now as requested"),
+		}
 	}
 }
 
-#[allow(clippy::or_fun_call)]
-async fn check_weekday_pdf(day: Weekdays, pdf_getter: Arc<SubstitutionPDFGetter<'_>>, discord: Arc<DiscordNotifier>, datastore: Arc<Data>) -> Result<(), Box<dyn std::error::Error>> {
+/// Checks the PDF of the day, which is for the upcoming date of the day unless it's outdated or for a later date
+#[allow(clippy::or_fun_call, clippy::too_many_arguments)]
+async fn check_weekday_pdf(day: Weekdays, upcoming_date: NaiveDate, pdf_getter: Arc<dyn ScheduleSource>, discord: Arc<DiscordNotifier>, datastore: Arc<Data>, calendar: Arc<HolidayCalendar>, timezone: Tz, debounce: Option<Debounce>) -> Result<(), Box<dyn std::error::Error>> {
 	info!("Checking PDF for {}", day);
-	let temp_dir_path = util::make_temp_dir();
-	let temp_file_name = util::get_random_name();
-	let temp_file_path = format!("{}/{}", temp_dir_path, temp_file_name);
-	let temp_file_path = Path::new(&temp_file_path);
-
-	let pdf = pdf_getter.get_weekday_pdf(day).await?;
-	let mut temp_pdf_file = std::fs::File::create(temp_file_path).expect("Couldn't create temp pdf file");
-	temp_pdf_file.write_all(&pdf)?;
-	let new_schedule = SubstitutionSchedule::from_pdf(temp_file_path)?;
-
-	// Check the date in the pdf and if it is too old delete the file (if it exists) and return.
-	if new_schedule.pdf_create_date < chrono::Local::today().and_hms_milli(0, 0, 0, 0).timestamp_millis() {
-		log::info!("Deleting old pdf for day {}", &day);
-		datastore.delete_pdf_json(day)?;
+
+	let pdf = match pdf_getter.get_weekday_pdf(day).await? {
+		PdfFetch::Changed(pdf) => pdf,
+		PdfFetch::Unchanged => {
+			let date = pdf_getter.date_override(day, school_time::today(timezone)).unwrap_or(upcoming_date);
+			return notify_settled_revision(date, &discord, &datastore, debounce).await;
+		}
+	};
+	info!("PDF for {} changed, SHA-256 {}", day, pdf.sha256());
+
+	let new_schedule = parse_fetched_pdf(&pdf, pdf_getter.as_ref(), timezone)?;
+
+	// Parsing the same PDF again won't help, so it's only tried again once it changes
+	if new_schedule.is_err() {
+		pdf_getter.mark_processed(&pdf);
+	}
+	let new_schedule = new_schedule?;
+
+	// Check the date in the pdf and if it is too old or not a school day delete the file (if it exists) and return.
+	let date = new_schedule.get_date();
+	if is_stale(date, &calendar, school_time::today(timezone)) {
+		log::info!("Deleting old pdf for day {} on {} or one for a day without school", &day, date);
+		datastore.delete_pdf_json(date)?;
+		pdf_getter.mark_processed(&pdf);
 		return Ok(());
 	}
 
+	let new_schedule_json = serde_json::to_string_pretty(&new_schedule).expect("Couldn't write the new Json");
+	let revision = ArchivedRevision {
+		sha256: pdf.sha256().to_owned(),
+		fetched_at: pdf.fetched_at,
+		date: new_schedule.pdf_create_date,
+	};
+	// The pending revision keeps its own schedule, so the users are still notified if archiving fails
+	if let Err(why) = datastore.archive_revision(&revision, &pdf.body, &new_schedule_json) {
+		log::error!("Couldn't archive the PDF for {}: {}", day, why);
+	}
+
 	if let Err(why) = datastore.update_class_whitelist(&new_schedule.get_classes()) {
 		log::error!("{}", why);
 	}
 
-	let old_schedule_option: Option<SubstitutionSchedule> = {
-		match datastore.get_pdf_json(day) {
-			Ok(content) => {
-				log::trace!("old_schedule_option datastore pdf was Ok");
-				match serde_json::from_str(content.as_str()) {
-					Ok(old_schedule) => Some(old_schedule),
-					Err(why) => {
-						log::error!("{}", why);
-						None
-					}
+	let old_schedule_option = load_notified_schedule(&datastore, date);
+	let diff = ScheduleDiff::between(old_schedule_option.as_ref(), &new_schedule);
+
+	if let Some(debounce) = debounce {
+		if !debounce.is_urgent(old_schedule_option.as_ref(), &diff, &new_schedule, Utc::now().timestamp_millis()) {
+			info!("Notifying about the plan for {} once it stops changing", date);
+			datastore.set_pending_revision(&PendingRevision {
+				revision: revision.clone(),
+				schedule_json: Some(new_schedule_json),
+			})?;
+			pdf_getter.mark_processed(&pdf);
+			return Ok(());
+		}
+	}
+
+	notify_changes(date, old_schedule_option.as_ref(), &new_schedule, &diff, &discord).await?;
+
+	datastore.store_pdf_json(date, new_schedule_json.as_str())?;
+	datastore.remove_pending_revision(revision.date)?;
+	pdf_getter.mark_processed(&pdf);
+
+	Ok(())
+}
+
+/// Parses the changed PDF from a temp file. The outer error is about the temp file, the inner one about the PDF.
+/// Sources that decide the date of the plan themselves, e.g. the replay of old fixtures, move the plan to that date.
+fn parse_fetched_pdf(pdf: &FetchedPdf, source: &dyn ScheduleSource, timezone: Tz) -> std::io::Result<Result<SubstitutionSchedule, ParseError>> {
+	// The temp directory is only created for a changed PDF, unchanged ones are checked on almost every poll
+	let temp_dir_path = util::make_temp_dir();
+	let temp_file_path = Path::new(&temp_dir_path).join(util::get_random_name());
+	let new_schedule = std::fs::write(&temp_file_path, &pdf.body)
+		.map(|_| SubstitutionSchedule::from_pdf(&temp_file_path, pdf.tabula_capture.as_deref(), timezone));
+
+	// Remove the temp files before checking the result, so a malformed PDF or a failed write doesn't leave them behind
+	std::fs::remove_dir_all(&temp_dir_path)?;
+
+	Ok(new_schedule?.map(|mut new_schedule| {
+		if let Some(date) = source.date_override(pdf.day, school_time::today(timezone)) {
+			info!("Replaying the plan for {} on {}", new_schedule.get_date(), date);
+			new_schedule.pdf_create_date = school_time::date_to_millis(date);
+		}
+		new_schedule
+	}))
+}
+
+/// Plans of past days and of days without school are of no use
+fn is_stale(date: NaiveDate, calendar: &HolidayCalendar, today: NaiveDate) -> bool {
+	date < today || !calendar.is_school_day(date)
+}
+
+/// Notifies about the pending revision of the date once its plan stopped changing, about everything that changed since
+/// the plan the users were notified about last
+async fn notify_settled_revision(date: NaiveDate, discord: &DiscordNotifier, datastore: &Data, debounce: Option<Debounce>) -> Result<(), Box<dyn std::error::Error>> {
+	let new_schedule_json = match settled_schedule_json(datastore, date, debounce, Utc::now().timestamp_millis())? {
+		Some(new_schedule_json) => new_schedule_json,
+		None => return Ok(()),
+	};
+	let new_schedule: SubstitutionSchedule = serde_json::from_str(&new_schedule_json)?;
+	info!("The plan for {} stopped changing", date);
+
+	let old_schedule_option = load_notified_schedule(datastore, date);
+	let diff = ScheduleDiff::between(old_schedule_option.as_ref(), &new_schedule);
+	notify_changes(date, old_schedule_option.as_ref(), &new_schedule, &diff, discord).await?;
+
+	datastore.store_pdf_json(date, &new_schedule_json)?;
+	datastore.remove_pending_revision(school_time::date_to_millis(date))?;
+
+	Ok(())
+}
+
+/// The schedule of the pending revision of the date if its plan stopped changing
+fn settled_schedule_json(datastore: &Data, date: NaiveDate, debounce: Option<Debounce>, now: i64) -> Result<Option<String>, Box<dyn std::error::Error>> {
+	let date_millis = school_time::date_to_millis(date);
+	let pending = datastore.get_pending_revisions()?
+		.into_iter()
+		.find(|pending| pending.revision.date == date_millis);
+	let pending = match pending {
+		Some(pending) if debounce.is_none_or(|debounce| debounce.is_settled(pending.revision.fetched_at, now)) => pending,
+		_ => return Ok(None),
+	};
+
+	match pending.schedule_json {
+		Some(schedule_json) => Ok(Some(schedule_json)),
+		None => Ok(Some(datastore.get_archived_schedule_json(&pending.revision.sha256)?)),
+	}
+}
+
+/// The plan of the date the users were notified about last
+fn load_notified_schedule(datastore: &Data, date: NaiveDate) -> Option<SubstitutionSchedule> {
+	match datastore.get_pdf_json(date) {
+		Ok(content) => {
+			log::trace!("old_schedule_option datastore pdf was Ok");
+			match serde_json::from_str(content.as_str()) {
+				Ok(old_schedule) => Some(old_schedule),
+				Err(why) => {
+					log::error!("{}", why);
+					None
 				}
 			}
-			Err(_) => {
-				None
-			}
 		}
-	};
+		Err(_) => {
+			None
+		}
+	}
+}
 
+/// Notifies the users of every class that changed between the plans
+async fn notify_changes(date: NaiveDate, old_schedule_option: Option<&SubstitutionSchedule>, new_schedule: &SubstitutionSchedule, diff: &ScheduleDiff, discord: &DiscordNotifier) -> Result<(), Box<dyn std::error::Error>> {
 	let data = discord.data.read().await;
 
 	let classes_and_users = data.get::<ClassesAndUsers>().unwrap();
@@ -165,28 +339,145 @@ async fn check_weekday_pdf(day: Weekdays, pdf_getter: Arc<SubstitutionPDFGetter<
 		}
 	};
 
+	let new_metadata = new_schedule.get_metadata();
 	for class in classes_and_users_inner.keys() {
-		if let Some(new_substitutions) = new_schedule.get_substitutions(class.as_str()) {
-			if let Some(old_schedule) = &old_schedule_option {
-				if let Some(old_substitutions) = old_schedule.get_substitutions(class.as_str()) {
-					if new_substitutions != old_substitutions {
-						add_to_notify(class);
-					}
-				}
-			} else {
-				add_to_notify(class);
+		// Some changes, like a class being on an excursion, are only in the notices
+		match old_schedule_option {
+			Some(old_schedule) if old_schedule.get_metadata().differs_for_class(new_metadata, class) => add_to_notify(class),
+			None if new_metadata.mentions_class(class) => add_to_notify(class),
+			_ => {}
+		}
+
+		if diff.get_transition(class).is_some() {
+			add_to_notify(class);
+		}
+	}
+
+	drop(data);
+
+	discord.notify_users(date, new_schedule, diff, to_notify).await
+}
+
+/// Deletes the stored plans and pending revisions of past days and of days without school
+fn delete_stale_plans(datastore: &Data, calendar: &HolidayCalendar, today: NaiveDate) {
+	let dates = match datastore.get_pdf_json_dates() {
+		Ok(dates) => dates,
+		Err(why) => {
+			error!("{}", why);
+			return;
+		}
+	};
+
+	for date in dates {
+		if date < today || !calendar.is_school_day(date) {
+			info!("Deleting the stale plan for {}", date);
+			if let Err(why) = datastore.delete_pdf_json(date) {
+				error!("{}", why);
 			}
 		}
 	}
 
-	discord.notify_users(day, &new_schedule, to_notify).await?;
+	let pending = match datastore.get_pending_revisions() {
+		Ok(pending) => pending,
+		Err(why) => {
+			error!("{}", why);
+			return;
+		}
+	};
 
-	let new_schedule_json = serde_json::to_string_pretty(&new_schedule).expect("Couldn't write the new Json");
+	for pending in pending {
+		let date = school_time::millis_to_date(pending.revision.date);
+		if date < today || !calendar.is_school_day(date) {
+			info!("Dropping the pending revision for {}", date);
+			if let Err(why) = datastore.remove_pending_revision(pending.revision.date) {
+				error!("{}", why);
+			}
+		}
+	}
+}
 
-	datastore.store_pdf_json(day, new_schedule_json.as_str())?;
+#[cfg(test)]
+mod tests {
+	use chrono::{Duration, Weekday};
 
-	std::fs::remove_file(temp_file_path)?;
-	std::fs::remove_dir(temp_dir_path)?;
+	use crate::config::Notifications;
+	use crate::schedule_source::{DirectorySource, ReplaySource};
 
-	Ok(())
-}
\ No newline at end of file
+	use super::*;
+
+	fn pending(sha256: &str, fetched_at: i64, date: NaiveDate, schedule_json: &str) -> PendingRevision {
+		PendingRevision {
+			revision: ArchivedRevision {
+				sha256: sha256.to_owned(),
+				fetched_at,
+				date: school_time::date_to_millis(date),
+			},
+			schedule_json: Some(schedule_json.to_owned()),
+		}
+	}
+
+	#[test]
+	fn test_settled_schedule_json_without_archive() {
+		let data_directory = format!("/tmp/test-{}", util::get_random_name());
+		let datastore = Data::new(data_directory.clone()).unwrap();
+		// Writing the archived PDF fails
+		let archived_pdfs = format!("{}/archive/pdfs", data_directory);
+		std::fs::remove_dir_all(&archived_pdfs).unwrap();
+		std::fs::write(&archived_pdfs, "").unwrap();
+		let date = NaiveDate::from_ymd(2020, 5, 28);
+		let revision = pending("aaa", 1_000, date, "{ \"parsed\": \"now\" }");
+
+		assert!(datastore.archive_revision(&revision.revision, b"%PDF", "{}").is_err());
+		datastore.set_pending_revision(&revision).unwrap();
+
+		let notifications = Notifications {
+			debounce_minutes: 10,
+			..Notifications::default()
+		};
+		let debounce = Debounce::from_config(&notifications, school_time::DEFAULT_TIMEZONE);
+		assert_eq!(settled_schedule_json(&datastore, date, debounce, 1_000 + 60_000).unwrap(), None);
+		assert_eq!(settled_schedule_json(&datastore, date, debounce, 1_000 + 600_000).unwrap(), Some("{ \"parsed\": \"now\" }".to_owned()));
+	}
+
+	#[test]
+	fn test_settled_schedule_json_of_the_date() {
+		let datastore = Data::new(format!("/tmp/test-{}", util::get_random_name())).unwrap();
+		let monday = NaiveDate::from_ymd(2020, 5, 25);
+		let next_monday = monday + Duration::days(7);
+
+		datastore.set_pending_revision(&pending("aaa", 1_000, monday, "{ \"date\": \"monday\" }")).unwrap();
+		datastore.set_pending_revision(&pending("bbb", 2_000, next_monday, "{ \"date\": \"next monday\" }")).unwrap();
+
+		assert_eq!(settled_schedule_json(&datastore, next_monday, None, 3_000).unwrap(), Some("{ \"date\": \"next monday\" }".to_owned()));
+		assert_eq!(settled_schedule_json(&datastore, monday, None, 3_000).unwrap(), Some("{ \"date\": \"monday\" }".to_owned()));
+		assert_eq!(settled_schedule_json(&datastore, monday + Duration::days(1), None, 3_000).unwrap(), None);
+
+		// A revision from before the schedule was kept with it uses the archived one
+		let archived = pending("ccc", 3_000, monday, "{ \"date\": \"archived monday\" }");
+		datastore.archive_revision(&archived.revision, b"%PDF", "{ \"date\": \"archived monday\" }").unwrap();
+		datastore.set_pending_revision(&PendingRevision { schedule_json: None, ..archived }).unwrap();
+		assert_eq!(settled_schedule_json(&datastore, monday, None, 4_000).unwrap(), Some("{ \"date\": \"archived monday\" }".to_owned()));
+	}
+
+	#[tokio::test]
+	async fn test_replayed_plan_is_checked_as_the_plan_of_the_day() {
+		std::fs::create_dir_all(TEMP_ROOT_DIR).unwrap();
+		let source = ReplaySource::new(Path::new("./sample_plans")).unwrap();
+		let timezone = school_time::DEFAULT_TIMEZONE;
+		let today = school_time::today(timezone);
+
+		let pdf = match source.get_weekday_pdf(Weekdays::Wednesday).await.unwrap() {
+			PdfFetch::Changed(pdf) => pdf,
+			PdfFetch::Unchanged => panic!("Expected the first fixture"),
+		};
+
+		let date = parse_fetched_pdf(&pdf, &source, timezone).unwrap().unwrap().get_date();
+		assert_eq!(date.weekday(), Weekday::Wed);
+		assert!(date >= today && date < today + Duration::days(7));
+		assert!(!is_stale(date, &HolidayCalendar::default(), today));
+
+		// Other sources keep the date of the PDF, which is long gone
+		let date = parse_fetched_pdf(&pdf, &DirectorySource::new("."), timezone).unwrap().unwrap().get_date();
+		assert!(is_stale(date, &HolidayCalendar::default(), today));
+	}
+}
@@ -0,0 +1,569 @@
+use std::collections::BTreeMap;
+
+use lopdf::{Document, Object, ObjectId};
+use lopdf::content::Operation;
+
+use crate::error::ParseError;
+
+/// The text of the top left cell of every substitution table, used to find the table headers.
+const TABLE_HEADER_FIRST_CELL: &str = "Block";
+/// The approximate height of a text line relative to its font size.
+/// Text whose vertical extents overlap is put into the same row.
+const TEXT_HEIGHT_FACTOR: f64 = 0.5;
+/// How far apart (in pt) two coordinates may be to still be considered the same.
+const POSITION_TOLERANCE: f64 = 0.5;
+
+/// Extracts the substitution tables from every page of the PDF.
+/// The output has the same shape as the output of `tabula_json_parser::parse`:
+/// A Vector of tables, which contain the rows, which contain the text of every cell.
+pub fn extract_tables(document: &Document) -> Result<Vec<Vec<Vec<String>>>, ParseError> {
+	let mut tables = Vec::new();
+
+	for page_id in document.get_pages().values() {
+		let page = PageContent::from_page(document, *page_id)?;
+		tables.extend(page.extract_tables());
+	}
+
+	Ok(tables)
+}
+
+/// Extracts the lines of text outside of the substitution tables, e.g. the heading and the notices below the tables.
+/// Returns the lines of every page from the top of the page to the bottom, the text of a line is joined with ' '.
+pub fn extract_text_outside_tables(document: &Document) -> Result<Vec<Vec<String>>, ParseError> {
+	let mut pages = Vec::new();
+
+	for page_id in document.get_pages().values() {
+		let page = PageContent::from_page(document, *page_id)?;
+		pages.push(page.text_outside_tables());
+	}
+
+	Ok(pages)
+}
+
+/// A 2D affine transformation matrix in the PDF notation `[a b c d e f]`
+#[derive(Debug, Clone, Copy)]
+struct Matrix([f64; 6]);
+
+impl Matrix {
+	fn identity() -> Self {
+		Self([1.0, 0.0, 0.0, 1.0, 0.0, 0.0])
+	}
+
+	fn translation(x: f64, y: f64) -> Self {
+		Self([1.0, 0.0, 0.0, 1.0, x, y])
+	}
+
+	/// Returns the matrix that first applies `self` and then `other`
+	fn then(&self, other: &Self) -> Self {
+		let [a, b, c, d, e, f] = self.0;
+		let [oa, ob, oc, od, oe, of] = other.0;
+		Self([
+			a * oa + b * oc,
+			a * ob + b * od,
+			c * oa + d * oc,
+			c * ob + d * od,
+			e * oa + f * oc + oe,
+			e * ob + f * od + of,
+		])
+	}
+
+	fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+		let [a, b, c, d, e, f] = self.0;
+		(a * x + c * y + e, b * x + d * y + f)
+	}
+
+	/// The vertical scaling factor, used to get the rendered font size
+	fn vertical_scale(&self) -> f64 {
+		let [_, _, c, d, _, _] = self.0;
+		(c * c + d * d).sqrt()
+	}
+}
+
+/// A piece of text drawn with a single text showing operator
+#[derive(Debug, Clone)]
+struct TextChunk {
+	/// The x coordinate of the start of the baseline
+	x: f64,
+	/// The y coordinate of the baseline, from the bottom of the page
+	y: f64,
+	font_size: f64,
+	text: String,
+}
+
+impl TextChunk {
+	fn top(&self) -> f64 {
+		self.y + self.font_size * TEXT_HEIGHT_FACTOR
+	}
+}
+
+/// A straight line drawn on the page, either by a path or as the edge of a rectangle
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+	x0: f64,
+	y0: f64,
+	x1: f64,
+	y1: f64,
+}
+
+impl Segment {
+	fn is_vertical(&self) -> bool {
+		(self.x0 - self.x1).abs() < POSITION_TOLERANCE
+	}
+
+	fn bottom(&self) -> f64 {
+		self.y0.min(self.y1)
+	}
+
+	fn top(&self) -> f64 {
+		self.y0.max(self.y1)
+	}
+}
+
+/// A rectangle with a non negative width and height
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+	x: f64,
+	y: f64,
+	width: f64,
+	height: f64,
+}
+
+impl Rect {
+	fn contains(&self, x: f64, y: f64) -> bool {
+		x >= self.x && x <= self.x + self.width && y >= self.y && y <= self.y + self.height
+	}
+}
+
+/// The graphics state parts we need to know where the text ends up
+#[derive(Debug, Clone)]
+struct GraphicsState {
+	ctm: Matrix,
+	font: Vec<u8>,
+	font_size: f64,
+	leading: f64,
+}
+
+/// A table column, spanning from `left` to `right`
+#[derive(Debug)]
+struct Column {
+	left: f64,
+	right: f64,
+}
+
+/// Everything drawn on a page that is relevant for finding the tables
+#[derive(Debug, Default)]
+struct PageContent {
+	chunks: Vec<TextChunk>,
+	rects: Vec<Rect>,
+	segments: Vec<Segment>,
+}
+
+impl PageContent {
+	fn from_page(document: &Document, page_id: ObjectId) -> Result<Self, ParseError> {
+		let encodings = document.get_page_fonts(page_id)
+			.into_iter()
+			.map(|(name, font)| (name, font.get_font_encoding().to_owned()))
+			.collect::<BTreeMap<Vec<u8>, String>>();
+		let content = document.get_and_decode_page_content(page_id).map_err(|why| ParseError::Extractor(why.to_string()))?;
+
+		let mut page = Self::default();
+		let mut state = GraphicsState {
+			ctm: Matrix::identity(),
+			font: Vec::new(),
+			font_size: 0.0,
+			leading: 0.0,
+		};
+		let mut state_stack = Vec::new();
+		let mut text_matrix = Matrix::identity();
+		let mut line_matrix = Matrix::identity();
+		let mut current_point = (0.0, 0.0);
+
+		for operation in &content.operations {
+			let operands = numeric_operands(operation);
+			match operation.operator.as_str() {
+				"q" => state_stack.push(state.clone()),
+				"Q" => {
+					if let Some(previous) = state_stack.pop() {
+						state = previous;
+					}
+				}
+				"cm" => {
+					if let [a, b, c, d, e, f] = operands[..] {
+						state.ctm = Matrix([a, b, c, d, e, f]).then(&state.ctm);
+					}
+				}
+				"re" => {
+					if let [x, y, width, height] = operands[..] {
+						page.add_rect(&state.ctm, x, y, width, height);
+					}
+				}
+				"m" => {
+					if let [x, y] = operands[..] {
+						current_point = (x, y);
+					}
+				}
+				"l" => {
+					if let [x, y] = operands[..] {
+						let (x0, y0) = state.ctm.apply(current_point.0, current_point.1);
+						let (x1, y1) = state.ctm.apply(x, y);
+						page.segments.push(Segment { x0, y0, x1, y1 });
+						current_point = (x, y);
+					}
+				}
+				"BT" => {
+					text_matrix = Matrix::identity();
+					line_matrix = Matrix::identity();
+				}
+				"Tf" => {
+					if let Some(Object::Name(font)) = operation.operands.first() {
+						state.font = font.clone();
+					}
+					if let Some(size) = operands.last() {
+						state.font_size = *size;
+					}
+				}
+				"TL" => {
+					if let [leading] = operands[..] {
+						state.leading = leading;
+					}
+				}
+				"Td" | "TD" => {
+					if let [x, y] = operands[..] {
+						if operation.operator == "TD" {
+							state.leading = -y;
+						}
+						line_matrix = Matrix::translation(x, y).then(&line_matrix);
+						text_matrix = line_matrix;
+					}
+				}
+				"Tm" => {
+					if let [a, b, c, d, e, f] = operands[..] {
+						line_matrix = Matrix([a, b, c, d, e, f]);
+						text_matrix = line_matrix;
+					}
+				}
+				"T*" => {
+					line_matrix = Matrix::translation(0.0, -state.leading).then(&line_matrix);
+					text_matrix = line_matrix;
+				}
+				"Tj" | "TJ" | "'" | "\"" => {
+					if operation.operator != "Tj" && operation.operator != "TJ" {
+						line_matrix = Matrix::translation(0.0, -state.leading).then(&line_matrix);
+						text_matrix = line_matrix;
+					}
+
+					let encoding = encodings.get(&state.font).map(String::as_str);
+					let mut text = String::new();
+					collect_text(&mut text, encoding, &operation.operands);
+
+					let text = text.trim();
+					if !text.is_empty() {
+						let matrix = text_matrix.then(&state.ctm);
+						let (x, y) = matrix.apply(0.0, 0.0);
+						page.chunks.push(TextChunk {
+							x,
+							y,
+							font_size: state.font_size * matrix.vertical_scale(),
+							text: text.to_owned(),
+						});
+					}
+				}
+				_ => {}
+			}
+		}
+
+		Ok(page)
+	}
+
+	fn add_rect(&mut self, ctm: &Matrix, x: f64, y: f64, width: f64, height: f64) {
+		let (x0, y0) = ctm.apply(x, y);
+		let (x1, y1) = ctm.apply(x + width, y + height);
+		let rect = Rect {
+			x: x0.min(x1),
+			y: y0.min(y1),
+			width: (x1 - x0).abs(),
+			height: (y1 - y0).abs(),
+		};
+
+		let (left, right, bottom, top) = (rect.x, rect.x + rect.width, rect.y, rect.y + rect.height);
+		self.segments.push(Segment { x0: left, y0: bottom, x1: left, y1: top });
+		self.segments.push(Segment { x0: right, y0: bottom, x1: right, y1: top });
+		self.segments.push(Segment { x0: left, y0: bottom, x1: right, y1: bottom });
+		self.segments.push(Segment { x0: left, y0: top, x1: right, y1: top });
+		self.rects.push(rect);
+	}
+
+	/// Groups the text into rows, from the top of the page to the bottom.
+	/// Chunks whose vertical extents overlap end up in the same row.
+	fn rows(&self) -> Vec<Vec<&TextChunk>> {
+		let mut chunks = self.chunks.iter().collect::<Vec<&TextChunk>>();
+		chunks.sort_by(|a, b| b.y.partial_cmp(&a.y).unwrap_or(std::cmp::Ordering::Equal));
+
+		let mut rows: Vec<Vec<&TextChunk>> = Vec::new();
+		let mut row_bottom = f64::INFINITY;
+		for chunk in chunks {
+			match rows.last_mut() {
+				Some(row) if chunk.top() > row_bottom => row.push(chunk),
+				_ => rows.push(vec![chunk]),
+			}
+			// The chunks are sorted from top to bottom, so the last one is always the lowest
+			row_bottom = chunk.y;
+		}
+
+		for row in &mut rows {
+			row.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal));
+		}
+
+		rows
+	}
+
+	fn extract_tables(&self) -> Vec<Vec<Vec<String>>> {
+		let rows = self.rows();
+		let mut tables = Vec::new();
+
+		for (header_idx, header) in rows.iter().enumerate() {
+			if !is_table_header(header) {
+				continue;
+			}
+
+			let columns = self.columns(header);
+			let table_left = columns.first().map_or(0.0, |column| column.left);
+			let table_right = columns.last().map_or(0.0, |column| column.right);
+			let table_bottom = self.table_area(header, &columns).y;
+
+			let mut table = vec![header.iter().map(|chunk| chunk.text.clone()).collect::<Vec<String>>()];
+
+			for row in &rows[header_idx + 1..] {
+				let row = row.iter()
+					.filter(|chunk| chunk.y > table_bottom)
+					.filter(|chunk| chunk.x >= table_left - POSITION_TOLERANCE && chunk.x <= table_right + POSITION_TOLERANCE)
+					.collect::<Vec<&&TextChunk>>();
+
+				if row.is_empty() {
+					continue;
+				}
+				if row[0].text == TABLE_HEADER_FIRST_CELL {
+					break;
+				}
+
+				let mut cells = vec![String::new(); columns.len()];
+				for chunk in row {
+					let cell = &mut cells[column_index(&columns, chunk.x)];
+					if !cell.is_empty() {
+						cell.push(' ');
+					}
+					cell.push_str(&chunk.text);
+				}
+				table.push(cells);
+			}
+
+			tables.push(table);
+		}
+
+		tables
+	}
+
+	/// Gets the text outside of the tables, every text chunk is its own line.
+	/// Texts on the same height are different lines as well, e.g. a heading on the left and the date on the right.
+	fn text_outside_tables(&self) -> Vec<String> {
+		let table_areas = self.rows()
+			.iter()
+			.filter(|row| is_table_header(row))
+			.map(|header| self.table_area(header, &self.columns(header)))
+			.collect::<Vec<Rect>>();
+
+		self.rows()
+			.into_iter()
+			.flatten()
+			.filter(|chunk| !table_areas.iter().any(|area| area.contains(chunk.x, chunk.y)))
+			.map(|chunk| chunk.text.trim().to_owned())
+			.collect()
+	}
+
+	/// The area covered by the table, from the top of the header to the bottom border, between the outer columns.
+	fn table_area(&self, header: &[&TextChunk], columns: &[Column]) -> Rect {
+		let left = columns.first().map_or(0.0, |column| column.left);
+		let right = columns.last().map_or(0.0, |column| column.right);
+		let top = self.rect_containing(header[0]).map_or(header[0].top(), |rect| rect.y + rect.height);
+		let header_bottom = self.rect_containing(header[0]).map_or(header[0].y, |rect| rect.y);
+		let bottom = self.table_bottom(left, right, header_bottom);
+
+		Rect {
+			x: left - POSITION_TOLERANCE,
+			y: bottom,
+			width: right - left + 2.0 * POSITION_TOLERANCE,
+			height: top - bottom + POSITION_TOLERANCE,
+		}
+	}
+
+	/// Gets the columns of the table from the header cells.
+	/// If the header cells have a border the border is used, otherwise the columns are split
+	/// in the middle between the header texts.
+	fn columns(&self, header: &[&TextChunk]) -> Vec<Column> {
+		header.iter()
+			.enumerate()
+			.map(|(i, chunk)| {
+				if let Some(rect) = self.rect_containing(chunk) {
+					return Column {
+						left: rect.x,
+						right: rect.x + rect.width,
+					};
+				}
+
+				let left = if i == 0 { chunk.x } else { (header[i - 1].x + chunk.x) / 2.0 };
+				let right = header.get(i + 1).map_or(f64::INFINITY, |next| (chunk.x + next.x) / 2.0);
+				Column {
+					left,
+					right,
+				}
+			})
+			.collect()
+	}
+
+	fn rect_containing(&self, chunk: &TextChunk) -> Option<&Rect> {
+		self.rects.iter()
+			.filter(|rect| rect.contains(chunk.x, chunk.y))
+			.min_by(|a, b| (a.width * a.height).partial_cmp(&(b.width * b.height)).unwrap_or(std::cmp::Ordering::Equal))
+	}
+
+	/// Follows the vertical cell borders of the table down from the header, the table ends where the borders end.
+	/// All borders are followed, since a row with a cell higher than the others leaves a gap in the border of the other cells.
+	/// Without a border the table extends to the bottom of the page.
+	fn table_bottom(&self, table_left: f64, table_right: f64, header_bottom: f64) -> f64 {
+		let borders = self.segments.iter()
+			.filter(|segment| segment.is_vertical())
+			.filter(|segment| segment.x0 > table_left - POSITION_TOLERANCE && segment.x0 < table_right + POSITION_TOLERANCE)
+			.collect::<Vec<&Segment>>();
+
+		if borders.is_empty() {
+			return f64::NEG_INFINITY;
+		}
+
+		let mut bottom = header_bottom;
+		while let Some(next) = borders.iter()
+			.filter(|segment| segment.top() >= bottom - POSITION_TOLERANCE && segment.bottom() < bottom - POSITION_TOLERANCE)
+			.map(|segment| segment.bottom())
+			.min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)) {
+			bottom = next;
+		}
+
+		bottom
+	}
+}
+
+fn is_table_header(row: &[&TextChunk]) -> bool {
+	row.first().map(|chunk| chunk.text.as_str()) == Some(TABLE_HEADER_FIRST_CELL)
+}
+
+/// Gets the index of the column the x coordinate is in, or the closest one.
+fn column_index(columns: &[Column], x: f64) -> usize {
+	if let Some(idx) = columns.iter().position(|column| x >= column.left - POSITION_TOLERANCE && x < column.right) {
+		return idx;
+	}
+
+	let distance = |column: &Column| (column.left - x).abs().min((column.right - x).abs());
+	columns.iter()
+		.enumerate()
+		.min_by(|(_, a), (_, b)| distance(a).partial_cmp(&distance(b)).unwrap_or(std::cmp::Ordering::Equal))
+		.map_or(0, |(idx, _)| idx)
+}
+
+fn numeric_operands(operation: &Operation) -> Vec<f64> {
+	#[allow(clippy::cast_precision_loss)]
+	operation.operands.iter()
+		.filter_map(|operand| match operand {
+			Object::Integer(value) => Some(*value as f64),
+			Object::Real(value) => Some(*value),
+			_ => None,
+		})
+		.collect()
+}
+
+fn collect_text(text: &mut String, encoding: Option<&str>, operands: &[Object]) {
+	for operand in operands {
+		match operand {
+			Object::String(bytes, _) => text.push_str(&Document::decode_text(encoding, bytes)),
+			Object::Array(array) => collect_text(text, encoding, array),
+			_ => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::tabula_json_parser::parse;
+
+	use super::*;
+
+	/// Tabula outputs rows without any text for cells only containing whitespace, we skip those.
+	fn without_empty_rows(tables: Vec<Vec<Vec<String>>>) -> Vec<Vec<Vec<String>>> {
+		tables.into_iter()
+			.map(|table| table.into_iter()
+				.filter(|row| row.iter().any(|cell| !cell.is_empty()))
+				.collect())
+			.collect()
+	}
+
+	#[test]
+	fn test_extract_tables_matches_tabula() {
+		let document = Document::load("./tabula/1337").unwrap();
+		let tables = extract_tables(&document).unwrap();
+
+		let tabula_tables = parse(&std::fs::read_to_string("./tabula/1337.json").unwrap()).unwrap();
+
+		assert_eq!(without_empty_rows(tabula_tables), tables);
+	}
+
+	#[test]
+	fn test_extract_tables_multiple_tables_matches_tabula() {
+		let document = Document::load("./tabula/86111").unwrap();
+		let tables = extract_tables(&document).unwrap();
+
+		// The capture only contains the tables of the first page
+		let tabula_tables = parse(&std::fs::read_to_string("./tabula/yes3.csv").unwrap()).unwrap();
+
+		assert_eq!(without_empty_rows(tabula_tables), tables[..3].to_vec());
+	}
+
+	#[test]
+	fn test_extract_text_outside_tables() {
+		let document = Document::load("./sample_plans/97840").unwrap();
+		let pages = extract_text_outside_tables(&document).unwrap();
+
+		assert_eq!(pages, vec![vec![
+			"Heinrich-Büssing-Schule Braunschweig".to_owned(),
+			"Stundenplan-Änderungen".to_owned(),
+			"Datum: Freitag, 13.3.2020".to_owned(),
+			"*Weitere Änderungen sind möglich.".to_owned(),
+			"Die AzuBis der Klassen FWM191 und EGS191 sind in die Betriebe".to_owned(),
+			"bestellt.".to_owned(),
+		]]);
+	}
+
+	#[test]
+	fn test_extract_tables_overflowing_cell() {
+		// The cell of BGYM192 in block 3 is higher than the other cells of the row, which leaves a gap in the left border
+		let document = Document::load("./sample_plans/97840").unwrap();
+		let tables = extract_tables(&document).unwrap();
+		let table = tables.iter().find(|table| table[0].last().map(String::as_str) == Some("BGYM192")).unwrap();
+
+		assert!(table.contains(&vec!["".to_owned(), "".to_owned(), "".to_owned(), "".to_owned(), "".to_owned(), "".to_owned(), "Vertretung".to_owned()]));
+		assert_eq!(table.last().unwrap()[0], "- 16:45");
+	}
+
+	#[test]
+	fn test_extract_tables_all_sample_plans() {
+		for entry in std::fs::read_dir("./sample_plans").unwrap() {
+			let path = entry.unwrap().path();
+			let document = Document::load(&path).unwrap();
+			let tables = extract_tables(&document).unwrap();
+
+			assert!(!tables.is_empty(), "No tables found in {:?}", path);
+			for table in tables {
+				assert_eq!(table[0][0], TABLE_HEADER_FIRST_CELL);
+				assert!(table[1..].iter().all(|row| row.len() == table[0].len()), "Uneven rows in {:?}", path);
+				assert!(table.last().unwrap()[0].starts_with('-'), "Table in {:?} doesn't end with a block end", path);
+			}
+		}
+	}
+}
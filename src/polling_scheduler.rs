@@ -0,0 +1,200 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime};
+use serenity::prelude::TypeMapKey;
+use tokio::sync::Notify;
+
+use crate::config::{Polling, PollingRule};
+use crate::holiday_calendar::HolidayCalendar;
+
+/// Decides when the plans are checked next, from the rules in [polling]
+pub struct PollingScheduler {
+	polling: Polling,
+	calendar: Arc<HolidayCalendar>,
+}
+
+impl PollingScheduler {
+	pub fn new(polling: Polling, calendar: Arc<HolidayCalendar>) -> Self {
+		Self {
+			polling,
+			calendar,
+		}
+	}
+
+	/// The interval of the first rule matching the time, or the default interval
+	pub fn interval_at(&self, time: NaiveDateTime) -> Duration {
+		let interval = self.polling.rules.iter()
+			.find(|rule| self.rule_applies(rule, time))
+			.map_or(self.polling.default_interval, |rule| rule.interval);
+
+		Duration::from_secs(interval)
+	}
+
+	/// The delay until the next check, at most until the next rule starts or ends, so e.g. the hourly checks at night
+	/// don't skip the start of the frequent checks in the morning.
+	/// Rules that don't apply on the day of the boundary are left out, so there are no extra checks on weekends and holidays.
+	/// `jitter` between 0 and 1 picks how much of the configured jitter is added.
+	pub fn next_delay(&self, now: NaiveDateTime, jitter: f64) -> Duration {
+		let delay = self.polling.rules.iter()
+			.flat_map(|rule| vec![(rule, next_time(now, rule.from)), (rule, next_time(now, rule.to))])
+			// The rule starts at the boundary or ends there
+			.filter(|(rule, boundary)| self.rule_applies(rule, *boundary) || self.rule_applies(rule, *boundary - chrono::Duration::seconds(1)))
+			.map(|(_, boundary)| (boundary - now).to_std().unwrap_or_default())
+			.fold(self.interval_at(now), Duration::min);
+
+		#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+			let jitter = (self.polling.jitter as f64 * jitter.clamp(0.0, 1.0)) as u64;
+		delay + Duration::from_secs(jitter)
+	}
+
+	fn rule_applies(&self, rule: &PollingRule, time: NaiveDateTime) -> bool {
+		rule_matches(rule, time) && (!rule.school_days || self.calendar.is_school_day(time.date()))
+	}
+}
+
+fn rule_matches(rule: &PollingRule, time: NaiveDateTime) -> bool {
+	let in_range = if rule.from <= rule.to {
+		rule.from <= time.time() && time.time() < rule.to
+	} else {
+		rule.from <= time.time() || time.time() < rule.to
+	};
+
+	in_range && (rule.days.is_empty() || rule.days.contains(&time.weekday()))
+}
+
+/// The next time the clock shows `time`, in a day if it shows it right now
+fn next_time(now: NaiveDateTime, time: NaiveTime) -> NaiveDateTime {
+	let next = now.date().and_time(time);
+	if next <= now {
+		next + chrono::Duration::days(1)
+	} else {
+		next
+	}
+}
+
+/// Lets other parts of the bot, e.g. a command, start a check right away instead of waiting for the next one.
+/// A poll fired during a check starts another one once it is done.
+#[derive(Default)]
+pub struct PollTrigger {
+	notify: Notify,
+}
+
+impl PollTrigger {
+	pub fn fire(&self) {
+		self.notify.notify_one();
+	}
+
+	/// Waits until the trigger is fired
+	pub async fn fired(&self) {
+		self.notify.notified().await;
+	}
+}
+
+impl TypeMapKey for PollTrigger {
+	type Value = Arc<PollTrigger>;
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::NaiveDate;
+
+	use crate::holiday_calendar::Holiday;
+
+	use super::*;
+
+	/// 18.02.2020 was a Tuesday
+	fn tuesday(hour: u32, minute: u32) -> NaiveDateTime {
+		NaiveDate::from_ymd(2020, 2, 18).and_hms(hour, minute, 0)
+	}
+
+	fn saturday(hour: u32, minute: u32) -> NaiveDateTime {
+		NaiveDate::from_ymd(2020, 2, 22).and_hms(hour, minute, 0)
+	}
+
+	fn scheduler(polling: Polling) -> PollingScheduler {
+		PollingScheduler::new(polling, Arc::new(HolidayCalendar::default()))
+	}
+
+	#[test]
+	fn test_interval_at() {
+		let scheduler = scheduler(Polling::default());
+
+		assert_eq!(scheduler.interval_at(tuesday(6, 0)), Duration::from_secs(20));
+		assert_eq!(scheduler.interval_at(tuesday(8, 29)), Duration::from_secs(20));
+		assert_eq!(scheduler.interval_at(tuesday(8, 30)), Duration::from_secs(300));
+		assert_eq!(scheduler.interval_at(tuesday(23, 0)), Duration::from_secs(3600));
+		assert_eq!(scheduler.interval_at(saturday(7, 0)), Duration::from_secs(3600));
+	}
+
+	#[test]
+	fn test_school_day_rules_skip_holidays() {
+		let calendar = HolidayCalendar::new(vec![Holiday {
+			name: "Fasching".to_owned(),
+			from: NaiveDate::from_ymd(2020, 2, 18),
+			to: NaiveDate::from_ymd(2020, 2, 18),
+		}]);
+		let scheduler = PollingScheduler::new(Polling::default(), Arc::new(calendar));
+
+		assert_eq!(scheduler.interval_at(tuesday(7, 0)), Duration::from_secs(3600));
+		assert_eq!(scheduler.interval_at(tuesday(7, 0) + chrono::Duration::days(1)), Duration::from_secs(20));
+	}
+
+	#[test]
+	fn test_rule_over_midnight() {
+		let rule = PollingRule {
+			days: Vec::new(),
+			school_days: false,
+			from: NaiveTime::from_hms(22, 0, 0),
+			to: NaiveTime::from_hms(6, 0, 0),
+			interval: 7200,
+		};
+
+		assert!(rule_matches(&rule, tuesday(23, 0)));
+		assert!(rule_matches(&rule, tuesday(5, 59)));
+		assert!(!rule_matches(&rule, tuesday(6, 0)));
+		assert!(!rule_matches(&rule, tuesday(12, 0)));
+	}
+
+	#[test]
+	fn test_next_delay_stops_at_rule_boundaries() {
+		let scheduler = scheduler(Polling {
+			jitter: 10,
+			..Polling::default()
+		});
+
+		// The hourly check at 5:45 would skip the first 45 minutes of the frequent checks
+		assert_eq!(scheduler.next_delay(tuesday(5, 45), 0.0), Duration::from_secs(15 * 60));
+		assert_eq!(scheduler.next_delay(tuesday(7, 0), 0.0), Duration::from_secs(20));
+		assert_eq!(scheduler.next_delay(tuesday(7, 0), 1.0), Duration::from_secs(30));
+		assert_eq!(scheduler.next_delay(tuesday(8, 28), 0.0), Duration::from_secs(20));
+		assert_eq!(scheduler.next_delay(saturday(12, 0), 0.5), Duration::from_secs(3605));
+	}
+
+	#[test]
+	fn test_next_delay_ignores_rules_of_other_days() {
+		let calendar = HolidayCalendar::new(vec![Holiday {
+			name: "Fasching".to_owned(),
+			from: NaiveDate::from_ymd(2020, 2, 18),
+			to: NaiveDate::from_ymd(2020, 2, 18),
+		}]);
+		let scheduler = PollingScheduler::new(Polling::default(), Arc::new(calendar));
+
+		// No school on the weekend and on the holiday, so the rules for school days don't start
+		assert_eq!(scheduler.next_delay(saturday(5, 45), 0.0), Duration::from_secs(3600));
+		assert_eq!(scheduler.next_delay(tuesday(5, 45), 0.0), Duration::from_secs(3600));
+		assert_eq!(scheduler.next_delay(tuesday(21, 45), 0.0), Duration::from_secs(3600));
+		// The frequent checks still start on the next school day and end on the last one
+		assert_eq!(scheduler.next_delay(tuesday(5, 45) + chrono::Duration::days(1), 0.0), Duration::from_secs(15 * 60));
+		assert_eq!(scheduler.next_delay(saturday(5, 45) - chrono::Duration::days(1), 0.0), Duration::from_secs(15 * 60));
+		assert_eq!(scheduler.next_delay(saturday(21, 59) - chrono::Duration::days(1), 0.0), Duration::from_secs(60));
+	}
+
+	#[tokio::test]
+	async fn test_poll_trigger_fired_before_waiting() {
+		let trigger = PollTrigger::default();
+
+		trigger.fire();
+		tokio::time::timeout(Duration::from_secs(1), trigger.fired()).await.expect("The trigger wasn't fired");
+	}
+}
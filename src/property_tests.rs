@@ -0,0 +1,135 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
+use serde_json::{json, Value};
+
+use crate::substitution_schedule::SubstitutionSchedule;
+use crate::tabula_json_parser;
+
+/// Inputs that made the parsers panic, see `fuzz/regressions`
+const REGRESSIONS_DIR: &str = "./fuzz/regressions";
+
+/// Cell texts like the ones in the plans, and anything else
+fn cell() -> impl Strategy<Value=String> {
+	prop_oneof![
+		Just(String::new()),
+		Just("nach Plan".to_owned()),
+		Just("----------".to_owned()),
+		"[A-ZÄÖÜ]{3} / [A-Z][0-9]{3}",
+		"Vertretung|Raumverleg\\.[A-Z]{3}|Aufgabenbetr\\.",
+		any::<String>(),
+	]
+}
+
+/// Labels of the first column, block starts and ends in any order and anything else
+fn label() -> impl Strategy<Value=String> {
+	prop_oneof![
+		Just(String::new()),
+		Just("Block".to_owned()),
+		(prop_oneof![0..10_u32, any::<u32>(), Just(u32::MAX)], "[0-9]{2}:[0-9]{2}").prop_map(|(block, time)| format!("{}:  {}", block, time)),
+		"- [0-9]{2}:[0-9]{2}",
+		any::<String>(),
+	]
+}
+
+/// Any grid, including ragged rows and tables without a header
+fn table() -> impl Strategy<Value=Vec<Vec<String>>> {
+	vec((label(), vec(cell(), 0..6)), 0..12).prop_map(|rows| rows.into_iter()
+		.map(|(label, cells)| std::iter::once(label).chain(cells).collect())
+		.collect())
+}
+
+/// A table like the ones in the plans: a header and blocks with a start, continuation lines and an end
+fn well_formed_table() -> impl Strategy<Value=Vec<Vec<String>>> {
+	(1..6_usize).prop_flat_map(|classes| {
+		let header = vec("[A-Z]{2,4}[0-9]{3}", classes);
+		let blocks = vec((0..8_u32, vec(vec(cell(), classes), 1..4)), 0..6);
+
+		(header, blocks).prop_map(|(header, blocks)| {
+			let mut table = vec![std::iter::once("Block".to_owned()).chain(header).collect::<Vec<String>>()];
+
+			for (block, rows) in blocks {
+				let last = rows.len() - 1;
+				for (idx, cells) in rows.into_iter().enumerate() {
+					let label = match idx {
+						0 => format!("{}:  08:00", block),
+						_ if idx == last => "- 09:30".to_owned(),
+						_ => String::new(),
+					};
+					table.push(std::iter::once(label).chain(cells).collect());
+				}
+				if last == 0 {
+					table.push(vec!["- 09:30".to_owned()]);
+				}
+			}
+
+			table
+		})
+	})
+}
+
+/// A tabula JSON capture with arbitrary cell geometry
+fn tabula_json() -> impl Strategy<Value=String> {
+	let geometry = prop_oneof![any::<f64>(), -1000.0..1000.0_f64, Just(0.0)];
+	let cell = (geometry.clone(), geometry.clone(), geometry.clone(), geometry, cell())
+		.prop_map(|(top, left, width, height, text)| json!({ "top": top, "left": left, "width": width, "height": height, "text": text }));
+	let table = vec(vec(cell, 0..6), 0..8).prop_map(|rows| json!({ "data": rows }));
+
+	vec(table, 0..3).prop_map(|tables| Value::Array(tables).to_string())
+}
+
+/// Checks that the schedule is the same after serializing and deserializing it
+fn assert_serde_round_trip(schedule: &SubstitutionSchedule) {
+	let json = serde_json::to_string(schedule).unwrap();
+	let deserialized: SubstitutionSchedule = serde_json::from_str(&json).unwrap();
+
+	assert_eq!(serde_json::to_value(schedule).unwrap(), serde_json::to_value(&deserialized).unwrap());
+}
+
+proptest! {
+	#[test]
+	fn test_table_to_substitutions_never_panics(table in table()) {
+		let _ = SubstitutionSchedule::table_to_substitutions(0, &table);
+	}
+
+	#[test]
+	fn test_from_table_round_trips_through_serde(tables in vec(table(), 0..3)) {
+		if let Ok(schedule) = SubstitutionSchedule::from_table(&tables, 0) {
+			assert_serde_round_trip(&schedule);
+		}
+	}
+
+	#[test]
+	fn test_well_formed_tables_parse(tables in vec(well_formed_table(), 1..3)) {
+		let schedule = SubstitutionSchedule::from_table(&tables, 0);
+
+		prop_assert!(schedule.is_ok(), "{:?}", schedule.err());
+		assert_serde_round_trip(&schedule.unwrap());
+	}
+
+	#[test]
+	fn test_tabula_parse_never_panics(content in tabula_json()) {
+		if let Ok(tables) = tabula_json_parser::parse(&content) {
+			let _ = SubstitutionSchedule::from_table(&tables, 0);
+		}
+	}
+
+	#[test]
+	fn test_tabula_parse_any_never_panics(content in any::<String>()) {
+		let _ = tabula_json_parser::parse_any(&content);
+	}
+}
+
+/// Runs every regression input through the parser and the schedule builder of its format
+#[test]
+fn test_regressions() {
+	for format_dir in std::fs::read_dir(REGRESSIONS_DIR).expect("Couldn't read the regressions directory") {
+		for entry in std::fs::read_dir(format_dir.unwrap().path()).unwrap() {
+			let path = entry.unwrap().path();
+			let content = std::fs::read_to_string(&path).unwrap();
+
+			if let Ok(schedule) = SubstitutionSchedule::from_tabula(&content, 0) {
+				assert_serde_round_trip(&schedule);
+			}
+		}
+	}
+}
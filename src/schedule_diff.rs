@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::substitution_schedule::{Substitution, Substitutions, SubstitutionSchedule};
+
+/// What happened to a block of a class between two revisions of a plan
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockChange {
+	/// The block had no substitution before
+	Added(Substitution),
+	/// The substitution of the block is gone
+	Removed(Substitution),
+	Modified {
+		old: Substitution,
+		new: Substitution,
+	},
+}
+
+impl BlockChange {
+	pub fn before(&self) -> Option<&Substitution> {
+		match self {
+			BlockChange::Added(_) => None,
+			BlockChange::Removed(old) | BlockChange::Modified { old, .. } => Some(old),
+		}
+	}
+
+	pub fn after(&self) -> Option<&Substitution> {
+		match self {
+			BlockChange::Removed(_) => None,
+			BlockChange::Added(new) | BlockChange::Modified { new, .. } => Some(new),
+		}
+	}
+}
+
+/// How the plan of a class changed, every class with a transition has to be notified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassTransition {
+	/// There was no plan for the day before
+	FirstPublication,
+	/// The class wasn't in the previous plan
+	NewClass,
+	/// The class isn't in the plan anymore
+	RemovedClass,
+	/// The class is still in the plan, but without any substitutions
+	Emptied,
+	/// Some blocks of the class changed
+	Changed,
+}
+
+/// The changed blocks of every class between two revisions of a plan, the unchanged ones are left out
+#[derive(Debug, Default, PartialEq)]
+pub struct ScheduleDiff {
+	classes: BTreeMap<String, BTreeMap<u32, BlockChange>>,
+	transitions: BTreeMap<String, ClassTransition>,
+}
+
+impl ScheduleDiff {
+	/// Compares the plans class by class and block by block.
+	/// A class that is only in one of the plans is compared with no substitutions at all.
+	/// Without an old plan every class in the new one is a first publication, without any block changes since everything is new.
+	pub fn between(old: Option<&SubstitutionSchedule>, new: &SubstitutionSchedule) -> Self {
+		let old = match old {
+			Some(old) => old,
+			None => return Self {
+				classes: BTreeMap::new(),
+				transitions: new.get_classes().into_iter().map(|class| (class, ClassTransition::FirstPublication)).collect(),
+			},
+		};
+		let mut classes = BTreeMap::new();
+		let mut transitions = BTreeMap::new();
+
+		for class in old.get_classes().union(&new.get_classes()) {
+			let old_substitutions = old.get_substitutions(class);
+			let new_substitutions = new.get_substitutions(class);
+			let changes = diff_substitutions(old_substitutions, new_substitutions);
+			if changes.is_empty() {
+				continue;
+			}
+
+			let transition = match new_substitutions {
+				_ if old_substitutions.is_none() => ClassTransition::NewClass,
+				None => ClassTransition::RemovedClass,
+				Some(substitutions) if substitutions.first_substitution().is_none() => ClassTransition::Emptied,
+				Some(_) => ClassTransition::Changed,
+			};
+			transitions.insert(class.clone(), transition);
+			classes.insert(class.clone(), changes);
+		}
+
+		Self {
+			classes,
+			transitions,
+		}
+	}
+
+	/// How the plan of the class changed, `None` if it didn't
+	pub fn get_transition(&self, class: &str) -> Option<ClassTransition> {
+		self.transitions.get(class).copied()
+	}
+
+	/// The changed blocks of the class, `None` if nothing changed for it
+	pub fn get_class_changes(&self, class: &str) -> Option<&BTreeMap<u32, BlockChange>> {
+		self.classes.get(class)
+	}
+
+	pub fn get(&self, class: &str, block: u32) -> Option<&BlockChange> {
+		self.classes.get(class).and_then(|changes| changes.get(&block))
+	}
+
+	/// The blocks that changed in any class
+	pub fn changed_blocks(&self) -> BTreeSet<u32> {
+		self.classes.values().flat_map(|changes| changes.keys().copied()).collect()
+	}
+}
+
+fn diff_substitutions(old: Option<&Substitutions>, new: Option<&Substitutions>) -> BTreeMap<u32, BlockChange> {
+	let mut changes = BTreeMap::new();
+
+	for (block, old_substitution) in old.iter().flat_map(|old| old.iter()) {
+		match new.and_then(|new| new.get(block)) {
+			Some(new_substitution) if new_substitution == old_substitution => {}
+			Some(new_substitution) => {
+				changes.insert(block, BlockChange::Modified {
+					old: old_substitution.clone(),
+					new: new_substitution.clone(),
+				});
+			}
+			None => {
+				changes.insert(block, BlockChange::Removed(old_substitution.clone()));
+			}
+		}
+	}
+
+	for (block, new_substitution) in new.iter().flat_map(|new| new.iter()) {
+		if old.and_then(|old| old.get(block)).is_none() {
+			changes.insert(block, BlockChange::Added(new_substitution.clone()));
+		}
+	}
+
+	changes
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn schedule(rows: &[&[&str]]) -> SubstitutionSchedule {
+		let table = rows.iter()
+			.map(|row| row.iter().map(|cell| cell.to_string()).collect())
+			.collect();
+
+		SubstitutionSchedule::from_table(&vec![table], 0).unwrap()
+	}
+
+	#[test]
+	fn test_diff_blocks() {
+		let old = schedule(&[
+			&["", "TSE191", "BGYM171"],
+			&["1: 07:15", "Vertretung\nFÄN / D208", ""],
+			&["- 08:00", "", ""],
+			&["2: 08:00", "----------", "Raumverleg.MÜL\n/ D208"],
+			&["- 09:30", "", ""],
+		]);
+		let new = schedule(&[
+			&["", "TSE191", "BGYM171"],
+			&["1: 07:15", "Vertretung FÄN\n/ D208", "----------"],
+			&["- 08:00", "", ""],
+			&["2: 08:00", "", "Raumverleg.MÜL\n/ D211"],
+			&["- 09:30", "", ""],
+		]);
+
+		let diff = ScheduleDiff::between(Some(&old), &new);
+
+		// Only the line wrapping changed
+		assert_eq!(diff.get("TSE191", 1), None);
+		assert_eq!(diff.get("TSE191", 2), Some(&BlockChange::Removed(Substitution::parse("----------"))));
+		assert_eq!(diff.get("BGYM171", 1), Some(&BlockChange::Added(Substitution::parse("----------"))));
+		assert_eq!(diff.get("BGYM171", 2), Some(&BlockChange::Modified {
+			old: Substitution::parse("Raumverleg.MÜL\n/ D208"),
+			new: Substitution::parse("Raumverleg.MÜL\n/ D211"),
+		}));
+		assert_eq!(diff.get_class_changes("BGYM171").map(BTreeMap::len), Some(2));
+		assert_eq!(diff.changed_blocks(), vec![1, 2].into_iter().collect());
+	}
+
+	#[test]
+	fn test_diff_classes_only_in_one_plan() {
+		let old = schedule(&[
+			&["", "TSE191"],
+			&["1: 07:15", "----------"],
+			&["- 08:00", ""],
+		]);
+		let new = schedule(&[
+			&["", "MB191"],
+			&["1: 07:15", "----------"],
+			&["- 08:00", ""],
+		]);
+
+		let diff = ScheduleDiff::between(Some(&old), &new);
+
+		assert_eq!(diff.get("TSE191", 1).and_then(BlockChange::after), None);
+		assert_eq!(diff.get("MB191", 1).and_then(BlockChange::before), None);
+		assert!(diff.get("MB191", 1).and_then(BlockChange::after).is_some());
+	}
+
+	#[test]
+	fn test_diff_of_the_same_plan_is_empty() {
+		let plan = [
+			&["", "TSE191"][..],
+			&["1: 07:15", "Vertretung\nFÄN / D208"],
+			&["- 08:00", ""],
+		];
+
+		assert_eq!(ScheduleDiff::between(Some(&schedule(&plan)), &schedule(&plan)), ScheduleDiff::default());
+	}
+
+	/// The plan before every transition, TSE191 and MB191 have substitutions, BGYM171 has none
+	fn previous_plan() -> SubstitutionSchedule {
+		schedule(&[
+			&["", "TSE191", "MB191", "BGYM171"],
+			&["1: 07:15", "----------", "Vertretung\nFÄN / D208", ""],
+			&["- 08:00", "", "", ""],
+		])
+	}
+
+	#[test]
+	fn test_transition_first_publication() {
+		let diff = ScheduleDiff::between(None, &previous_plan());
+
+		assert_eq!(diff.get_transition("TSE191"), Some(ClassTransition::FirstPublication));
+		// Even without substitutions, so its users know there are none
+		assert_eq!(diff.get_transition("BGYM171"), Some(ClassTransition::FirstPublication));
+		assert_eq!(diff.get_transition("FOS201"), None);
+		assert_eq!(diff.get_class_changes("TSE191"), None);
+	}
+
+	#[test]
+	fn test_transition_new_class() {
+		let new = schedule(&[
+			&["", "TSE191", "MB191", "BGYM171", "FOS201"],
+			&["1: 07:15", "----------", "Vertretung\nFÄN / D208", "", "----------"],
+			&["- 08:00", "", "", "", ""],
+		]);
+
+		let diff = ScheduleDiff::between(Some(&previous_plan()), &new);
+
+		assert_eq!(diff.get_transition("FOS201"), Some(ClassTransition::NewClass));
+		assert_eq!(diff.get_transition("TSE191"), None);
+	}
+
+	#[test]
+	fn test_transition_removed_class() {
+		let new = schedule(&[
+			&["", "MB191"],
+			&["1: 07:15", "Vertretung\nFÄN / D208"],
+			&["- 08:00", ""],
+		]);
+
+		let diff = ScheduleDiff::between(Some(&previous_plan()), &new);
+
+		assert_eq!(diff.get_transition("TSE191"), Some(ClassTransition::RemovedClass));
+		assert_eq!(diff.get("TSE191", 1), Some(&BlockChange::Removed(Substitution::parse("----------"))));
+		// Nothing changes for a class without substitutions
+		assert_eq!(diff.get_transition("BGYM171"), None);
+	}
+
+	#[test]
+	fn test_transition_emptied_class() {
+		let new = schedule(&[
+			&["", "TSE191", "MB191", "BGYM171"],
+			&["1: 07:15", "", "Vertretung\nFÄN / D208", ""],
+			&["- 08:00", "", "", ""],
+		]);
+
+		let diff = ScheduleDiff::between(Some(&previous_plan()), &new);
+
+		assert_eq!(diff.get_transition("TSE191"), Some(ClassTransition::Emptied));
+		assert_eq!(diff.get_transition("MB191"), None);
+	}
+
+	#[test]
+	fn test_transition_changed_class() {
+		let new = schedule(&[
+			&["", "TSE191", "MB191", "BGYM171"],
+			&["1: 07:15", "----------", "----------", ""],
+			&["- 08:00", "", "", ""],
+		]);
+
+		let diff = ScheduleDiff::between(Some(&previous_plan()), &new);
+
+		assert_eq!(diff.get_transition("MB191"), Some(ClassTransition::Changed));
+		assert_eq!(diff.get_transition("TSE191"), None);
+	}
+}
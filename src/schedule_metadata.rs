@@ -0,0 +1,293 @@
+use chrono::{FixedOffset, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::school_time;
+
+/// Lines that are on every plan and don't tell anything
+const BOILERPLATE_LINES: [&str; 1] = ["*Weitere Änderungen sind möglich."];
+/// The line with the date the plan is for, the lines above it are the heading of the page
+const DATE_PREFIX: &str = "Datum: ";
+/// The line with the time the plan was issued, e.g. "Stand: 18.02.2020 07:35"
+const ISSUED_AT_PREFIX: &str = "Stand: ";
+const ABSENT_TEACHERS_PREFIX: &str = "Abwesende Lehrer: ";
+const ABSENT_CLASSES_PREFIX: &str = "Abwesende Klassen: ";
+/// The end of the notice for classes whose apprentices are at their companies, e.g.
+/// "Die AzuBis der Klassen EIS171 und EGS191 sind in die Betriebe bestellt."
+const APPRENTICES_AT_COMPANIES: &str = "sind in die Betriebe bestellt";
+
+/// The information on the schedule PDF outside of the tables
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ScheduleMetadata {
+	/// The time the plan was issued in milliseconds, from the "Stand: " line or else the creation date of the PDF
+	pub issued_at: Option<i64>,
+	pub absent_teachers: Vec<String>,
+	/// The classes that are absent, including the ones whose apprentices are at their companies
+	pub absent_classes: Vec<String>,
+	/// Free text notices, e.g. "Die AzuBis der Klasse SH172 sind in die Betriebe bestellt."
+	pub notices: Vec<String>,
+}
+
+impl ScheduleMetadata {
+	/// Reads the metadata from the lines outside of the tables of every page.
+	/// The lines up to the "Datum: " line are the heading of the page and skipped.
+	/// Every page repeats the heading and the notices, so duplicates are skipped.
+	/// `pdf_creation_date` is the `CreationDate` of the PDF, e.g. "D:20200528075053", used when there is no "Stand: " line.
+	/// Times without an offset are in the timezone of the school.
+	pub fn parse(pages: &[Vec<String>], pdf_creation_date: Option<&str>, timezone: Tz) -> Self {
+		let mut metadata = Self::default();
+
+		for page in pages {
+			let content_start = page.iter()
+				.position(|line| line.starts_with(DATE_PREFIX))
+				.map_or(0, |idx| idx + 1);
+
+			for line in join_wrapped_lines(&page[content_start..]) {
+				metadata.add_line(&line, timezone);
+			}
+		}
+
+		if metadata.issued_at.is_none() {
+			metadata.issued_at = pdf_creation_date.and_then(|date| parse_pdf_date(date, timezone));
+		}
+
+		metadata
+	}
+
+	fn add_line(&mut self, line: &str, timezone: Tz) {
+		if BOILERPLATE_LINES.contains(&line) {
+			return;
+		}
+
+		if let Some(issued_at) = line.strip_prefix(ISSUED_AT_PREFIX) {
+			match parse_issued_at(issued_at, timezone) {
+				Some(issued_at) => self.issued_at = Some(issued_at),
+				None => log::warn!("Could not parse the issue time '{}'", line),
+			}
+		} else if let Some(teachers) = line.strip_prefix(ABSENT_TEACHERS_PREFIX) {
+			add_unique(&mut self.absent_teachers, split_list(teachers));
+		} else if let Some(classes) = line.strip_prefix(ABSENT_CLASSES_PREFIX) {
+			add_unique(&mut self.absent_classes, split_list(classes));
+		} else {
+			if line.contains(APPRENTICES_AT_COMPANIES) {
+				add_unique(&mut self.absent_classes, classes_in_notice(line));
+			}
+			add_unique(&mut self.notices, vec![line.to_owned()]);
+		}
+	}
+
+	pub fn is_class_absent(&self, class: &str) -> bool {
+		self.absent_classes.iter().any(|absent| absent == class)
+	}
+
+	/// The notices mentioning the class as a whole word, the plans sometimes write "TSE 191" for TSE191
+	pub fn notices_for_class(&self, class: &str) -> Vec<&str> {
+		self.notices.iter()
+			.filter(|notice| notice_mentions(notice, class))
+			.map(String::as_str)
+			.collect()
+	}
+
+	/// Whether the class is absent or mentioned in a notice
+	pub fn mentions_class(&self, class: &str) -> bool {
+		self.is_class_absent(class) || !self.notices_for_class(class).is_empty()
+	}
+
+	/// Whether the class became absent or present or a notice about it changed
+	pub fn differs_for_class(&self, other: &Self, class: &str) -> bool {
+		self.is_class_absent(class) != other.is_class_absent(class) || self.notices_for_class(class) != other.notices_for_class(class)
+	}
+}
+
+/// Long notices are wrapped over multiple lines, a line starting in lower case or following a ',' continues the previous one
+fn join_wrapped_lines(lines: &[String]) -> Vec<String> {
+	let mut joined: Vec<String> = Vec::new();
+
+	for line in lines {
+		let line = line.trim();
+		let continues = line.chars().next().is_some_and(char::is_lowercase);
+
+		match joined.last_mut() {
+			Some(previous) if continues || previous.ends_with(',') => {
+				previous.push(' ');
+				previous.push_str(line);
+			}
+			_ => joined.push(line.to_owned()),
+		}
+	}
+
+	joined
+}
+
+fn add_unique(list: &mut Vec<String>, entries: Vec<String>) {
+	for entry in entries {
+		if !list.contains(&entry) {
+			list.push(entry);
+		}
+	}
+}
+
+/// Splits a list like "ERE, FÄN und KLE" into its entries
+fn split_list(list: &str) -> Vec<String> {
+	list.split(',')
+		.flat_map(|part| part.split(" und "))
+		.map(str::trim)
+		.filter(|entry| !entry.is_empty())
+		.map(str::to_owned)
+		.collect()
+}
+
+/// Gets the classes from a notice like "Die AzuBis der Klassen TSE 191 und MB191 sind in die Betriebe bestellt."
+fn classes_in_notice(notice: &str) -> Vec<String> {
+	let start = notice.find("Klassen ")
+		.map(|idx| idx + "Klassen ".len())
+		.or_else(|| notice.find("Klasse ").map(|idx| idx + "Klasse ".len()));
+	let end = notice.find(APPRENTICES_AT_COMPANIES);
+
+	match (start, end) {
+		(Some(start), Some(end)) if start < end => split_list(&notice[start..end])
+			.iter()
+			.map(|class| without_whitespace(class))
+			.collect(),
+		_ => Vec::new(),
+	}
+}
+
+/// Whether the class is a word of the notice or two words in a row, e.g. "TSE 191".
+/// Only whole words count, so a notice about MB19 doesn't mention MB191.
+fn notice_mentions(notice: &str, class: &str) -> bool {
+	let words = notice.split_whitespace()
+		.map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+		.filter(|word| !word.is_empty())
+		.collect::<Vec<&str>>();
+
+	words.contains(&class)
+		|| words.windows(2).any(|pair| pair[0].len() + pair[1].len() == class.len() && class.starts_with(pair[0]) && class.ends_with(pair[1]))
+}
+
+fn without_whitespace(text: &str) -> String {
+	text.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+/// Parses the time after "Stand: ", e.g. "18.02.2020 07:35" into milliseconds
+fn parse_issued_at(issued_at: &str, timezone: Tz) -> Option<i64> {
+	let issued_at = issued_at.trim();
+	["%d.%m.%Y %H:%M:%S", "%d.%m.%Y %H:%M"].iter()
+		.find_map(|format| NaiveDateTime::parse_from_str(issued_at, format).ok())
+		.map(|time| school_time::local_to_millis(time, timezone))
+}
+
+/// Parses a PDF date like "D:20200528075053" or "D:20200528075053+02'00'" into milliseconds
+fn parse_pdf_date(date: &str, timezone: Tz) -> Option<i64> {
+	let date = date.strip_prefix("D:").unwrap_or(date);
+	let time = NaiveDateTime::parse_from_str(date.get(..14)?, "%Y%m%d%H%M%S").ok()?;
+
+	match pdf_date_offset(&date[14..]) {
+		Some(offset) => offset.from_local_datetime(&time).single().map(|time| time.timestamp_millis()),
+		None => Some(school_time::local_to_millis(time, timezone)),
+	}
+}
+
+/// The offset at the end of a PDF date, like "Z", "+02'00'" or "-05'30"
+fn pdf_date_offset(suffix: &str) -> Option<FixedOffset> {
+	let sign = match suffix.chars().next()? {
+		'Z' => return FixedOffset::east_opt(0),
+		'+' => 1,
+		'-' => -1,
+		_ => return None,
+	};
+	let digits = suffix[1..].chars().filter(char::is_ascii_digit).collect::<String>();
+	let hours = digits.get(..2)?.parse::<i32>().ok()?;
+	let minutes = digits.get(2..4).map_or(Some(0), |minutes| minutes.parse::<i32>().ok())?;
+
+	FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::NaiveDate;
+
+	use super::*;
+
+	fn lines(lines: &[&str]) -> Vec<String> {
+		lines.iter().map(|line| (*line).to_owned()).collect()
+	}
+
+	/// The milliseconds of a time in UTC
+	fn millis(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+		NaiveDate::from_ymd(year, month, day).and_hms(hour, minute, 0).timestamp_millis()
+	}
+
+	fn parse(pages: &[Vec<String>], pdf_creation_date: Option<&str>) -> ScheduleMetadata {
+		ScheduleMetadata::parse(pages, pdf_creation_date, school_time::DEFAULT_TIMEZONE)
+	}
+
+	#[test]
+	fn test_parse() {
+		let page = lines(&[
+			"Heinrich-Büssing-Schule Braunschweig",
+			"Stundenplan-Änderungen",
+			"Datum: Dienstag, 18.2.2020",
+			"Stand: 18.02.2020 07:35",
+			"Abwesende Lehrer: ERE, FÄN,",
+			"KLE und MÜS",
+			"Abwesende Klassen: BGYM171",
+			"*Weitere Änderungen sind möglich.",
+			"Die AzuBis der Klassen TSE 191 und MB191 sind in die Betriebe",
+			"bestellt.",
+		]);
+
+		let metadata = parse(&[page.clone(), page], Some("D:20200217150631"));
+
+		// 07:35 in Berlin is 06:35 in UTC in winter
+		assert_eq!(metadata, ScheduleMetadata {
+			issued_at: Some(millis(2020, 2, 18, 6, 35)),
+			absent_teachers: lines(&["ERE", "FÄN", "KLE", "MÜS"]),
+			absent_classes: lines(&["BGYM171", "TSE191", "MB191"]),
+			notices: lines(&["Die AzuBis der Klassen TSE 191 und MB191 sind in die Betriebe bestellt."]),
+		});
+		assert!(metadata.is_class_absent("TSE191"));
+		assert_eq!(metadata.notices_for_class("TSE191").len(), 1);
+		assert!(metadata.notices_for_class("TSP171").is_empty());
+		assert!(metadata.mentions_class("BGYM171"));
+		assert!(!metadata.mentions_class("TSP171"));
+	}
+
+	#[test]
+	fn test_differs_for_class() {
+		let old = parse(&[lines(&["Die AzuBis der Klasse SH172 sind in die Betriebe bestellt."])], None);
+		let new = parse(&[lines(&["Die AzuBis der Klassen SH172 und EIS171 sind in die Betriebe bestellt."])], None);
+
+		assert!(old.differs_for_class(&new, "EIS171"));
+		// The notice mentioning SH172 changed
+		assert!(old.differs_for_class(&new, "SH172"));
+		assert!(!old.differs_for_class(&new, "TSP171"));
+		assert!(!new.differs_for_class(&new.clone(), "EIS171"));
+	}
+
+	#[test]
+	fn test_notices_for_class_match_whole_classes() {
+		let metadata = parse(&[lines(&["Die Klasse MB19 hat heute frei.", "Die AzuBis der Klassen TSE 191, FWM191 sind in die Betriebe bestellt."])], None);
+
+		assert_eq!(metadata.notices_for_class("MB19"), vec!["Die Klasse MB19 hat heute frei."]);
+		assert!(metadata.notices_for_class("MB191").is_empty());
+		assert!(metadata.notices_for_class("TSE19").is_empty());
+		assert_eq!(metadata.notices_for_class("TSE191").len(), 1);
+		assert_eq!(metadata.notices_for_class("FWM191").len(), 1);
+		assert!(!metadata.mentions_class("B19"));
+	}
+
+	#[test]
+	fn test_parse_issued_at_falls_back_to_pdf_creation_date() {
+		let page = lines(&["Datum: Mittwoch, 18.3.2020", "Der Unterricht ist bis zum 18.04.2020 ausgesetzt."]);
+
+		let metadata = parse(&[page], Some("D:20200313135839"));
+
+		assert_eq!(metadata.issued_at, Some(millis(2020, 3, 13, 12, 58) + 39_000));
+		assert_eq!(metadata.notices, lines(&["Der Unterricht ist bis zum 18.04.2020 ausgesetzt."]));
+		assert!(metadata.absent_classes.is_empty());
+
+		assert_eq!(parse(&[], Some("D:2020")).issued_at, None);
+		assert_eq!(parse(&[], None), ScheduleMetadata::default());
+	}
+}
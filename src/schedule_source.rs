@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serenity::async_trait;
+use serenity::prelude::TypeMapKey;
+
+use crate::config::{Source, SourceKind};
+use crate::error::FetchError;
+use crate::substitution_pdf_getter::{SubstitutionPDFGetter, validate_pdf, Weekdays};
+use crate::util;
+
+/// Where the substitution PDFs come from
+#[async_trait]
+pub trait ScheduleSource: Send + Sync {
+	/// Gets the PDF of the day if it changed since the last one passed to `mark_processed`.
+	/// The outcome is recorded in the health of the day.
+	async fn get_weekday_pdf(&self, day: Weekdays) -> Result<PdfFetch, FetchError>;
+
+	/// Where the PDF of the day is read from, e.g. the URL, shown in the notifications
+	fn location(&self, day: Weekdays) -> String;
+
+	fn state(&self) -> &SourceState;
+
+	/// The date the plan of the day is for if the source decides it instead of the PDF
+	fn date_override(&self, _day: Weekdays, _today: NaiveDate) -> Option<NaiveDate> {
+		None
+	}
+
+	/// Remembers the PDF as processed, so it's only returned again once it changes.
+	/// Call this only once the PDF was handled, so it's tried again after e.g. a failed notification.
+	fn mark_processed(&self, pdf: &FetchedPdf) {
+		self.state().mark_processed(pdf);
+	}
+
+	/// The health of the source of every day that was read at least once, sorted by day
+	fn health(&self) -> Vec<(Weekdays, SourceHealth)> {
+		self.state().health()
+	}
+}
+
+/// The key of the source in the serenity data
+pub struct ScheduleSourceContainer;
+
+impl TypeMapKey for ScheduleSourceContainer {
+	type Value = Arc<dyn ScheduleSource>;
+}
+
+/// Creates the source configured in [source].
+/// Panics if it's misconfigured, e.g. the credentials or the path are missing, since we can't get any PDFs then.
+pub fn from_config(source: Source) -> Arc<dyn ScheduleSource> {
+	match source.kind {
+		SourceKind::Http => Arc::new(SubstitutionPDFGetter::new(source)),
+		SourceKind::Directory => Arc::new(DirectorySource::new(source.path.expect("The directory source needs a `path` in [source]"))),
+		SourceKind::Replay => {
+			let path = source.path.expect("The replay source needs a `path` in [source]");
+			Arc::new(ReplaySource::new(Path::new(&path)).expect("Couldn't read the fixtures of the replay source"))
+		}
+	}
+}
+
+/// What we know about the last processed PDF of a day, to only download and process it again if it changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchState {
+	pub etag: Option<String>,
+	pub last_modified: Option<String>,
+	/// The SHA-256 of the body, for servers that don't support conditional requests
+	pub sha256: String,
+}
+
+/// A PDF that differs from the last processed one of the day
+#[derive(Debug)]
+pub struct FetchedPdf {
+	pub day: Weekdays,
+	pub body: Vec<u8>,
+	/// A hand-fixed tabula capture of the tables of the PDF, used instead of extracting them
+	pub tabula_capture: Option<String>,
+	/// When the PDF was fetched in milliseconds
+	pub fetched_at: i64,
+	state: FetchState,
+}
+
+impl FetchedPdf {
+	fn new(day: Weekdays, body: Vec<u8>, etag: Option<String>, last_modified: Option<String>) -> Self {
+		let state = FetchState {
+			etag,
+			last_modified,
+			sha256: util::sha256_hex(&body),
+		};
+
+		Self {
+			day,
+			body,
+			tabula_capture: None,
+			fetched_at: Utc::now().timestamp_millis(),
+			state,
+		}
+	}
+
+	/// Uses the tables of the capture instead of the ones in the PDF, fixing the capture changes the PDF as well
+	fn with_tabula_capture(mut self, capture: String) -> Self {
+		let mut content = self.body.clone();
+		content.extend_from_slice(capture.as_bytes());
+		self.state.sha256 = util::sha256_hex(&content);
+		self.tabula_capture = Some(capture);
+		self
+	}
+
+	pub fn sha256(&self) -> &str {
+		&self.state.sha256
+	}
+}
+
+#[derive(Debug)]
+pub enum PdfFetch {
+	/// The server answered with 304 Not Modified or the body is the same as the last processed one
+	Unchanged,
+	Changed(FetchedPdf),
+}
+
+/// The processed PDFs and the health of every day, shared by all sources
+#[derive(Debug, Default)]
+pub struct SourceState {
+	/// The state of the last processed PDF of every day
+	processed: Mutex<HashMap<Weekdays, FetchState>>,
+	health: Mutex<HashMap<Weekdays, SourceHealth>>,
+}
+
+impl SourceState {
+	/// Returns the PDF as changed unless its SHA-256 is the same as the one of the last processed PDF of the day
+	pub fn compare_with_processed(&self, day: Weekdays, body: Vec<u8>, etag: Option<String>, last_modified: Option<String>) -> PdfFetch {
+		self.compare_fetched(FetchedPdf::new(day, body, etag, last_modified))
+	}
+
+	fn compare_fetched(&self, pdf: FetchedPdf) -> PdfFetch {
+		let day = pdf.day;
+
+		match self.processed_state(day) {
+			Some(processed) if processed.sha256 == pdf.state.sha256 => {
+				log::debug!("PDF for {} has the same SHA-256 as the last processed one", day);
+				// The validators may have changed while the content didn't, keep them for the next request
+				self.processed.lock().unwrap().insert(day, pdf.state);
+				PdfFetch::Unchanged
+			}
+			_ => PdfFetch::Changed(pdf),
+		}
+	}
+
+	pub fn mark_processed(&self, pdf: &FetchedPdf) {
+		self.processed.lock().unwrap().insert(pdf.day, pdf.state.clone());
+	}
+
+	pub fn processed_state(&self, day: Weekdays) -> Option<FetchState> {
+		self.processed.lock().unwrap().get(&day).cloned()
+	}
+
+	/// Records the outcome of getting the PDF of the day
+	pub fn record(&self, day: Weekdays, result: &Result<PdfFetch, FetchError>) {
+		let mut health = self.health.lock().unwrap();
+		let health = health.entry(day).or_default();
+
+		match result {
+			Ok(_) => health.record_success(),
+			Err(why) => health.record_failure(why),
+		}
+	}
+
+	pub fn health(&self) -> Vec<(Weekdays, SourceHealth)> {
+		let mut health = self.health.lock().unwrap()
+			.iter()
+			.map(|(day, health)| (*day, health.clone()))
+			.collect::<Vec<(Weekdays, SourceHealth)>>();
+		health.sort_by_key(|(day, _)| *day as usize);
+		health
+	}
+}
+
+/// How getting the PDFs of a day went
+#[derive(Debug, Clone, Default)]
+pub struct SourceHealth {
+	pub last_success: Option<DateTime<Utc>>,
+	/// The failures since the last success, retries of the same download don't count
+	pub consecutive_failures: u32,
+	pub last_error: Option<String>,
+	pub last_error_time: Option<DateTime<Utc>>,
+}
+
+impl SourceHealth {
+	fn record_success(&mut self) {
+		self.last_success = Some(Utc::now());
+		self.consecutive_failures = 0;
+	}
+
+	fn record_failure(&mut self, why: &FetchError) {
+		self.consecutive_failures += 1;
+		self.last_error = Some(why.to_string());
+		self.last_error_time = Some(Utc::now());
+	}
+}
+
+/// Reads the PDFs from `<directory>/<Weekday>.pdf`, e.g. "Monday.pdf".
+/// The files are read on every check and compared by their SHA-256, so replacing one is enough to get it processed.
+/// A tabula capture next to it, e.g. "Monday.csv" or "Monday.json", replaces the tables extracted from the PDF,
+/// for when an admin hand-fixed the extraction.
+pub struct DirectorySource {
+	directory: PathBuf,
+	state: SourceState,
+}
+
+impl DirectorySource {
+	pub fn new(directory: impl Into<PathBuf>) -> Self {
+		Self {
+			directory: directory.into(),
+			state: SourceState::default(),
+		}
+	}
+
+	fn path(&self, day: Weekdays) -> PathBuf {
+		self.directory.join(format!("{}.pdf", day))
+	}
+
+	async fn read_tabula_capture(&self, day: Weekdays) -> std::io::Result<Option<String>> {
+		for extension in &["csv", "json"] {
+			match tokio::fs::read_to_string(self.directory.join(format!("{}.{}", day, extension))).await {
+				Ok(capture) => return Ok(Some(capture)),
+				Err(why) if why.kind() == ErrorKind::NotFound => {}
+				Err(why) => return Err(why),
+			}
+		}
+
+		Ok(None)
+	}
+
+	async fn read_pdf(&self, day: Weekdays) -> Result<PdfFetch, FetchError> {
+		let body = match tokio::fs::read(self.path(day)).await {
+			Ok(body) => body,
+			Err(why) if why.kind() == ErrorKind::NotFound => {
+				log::debug!("There is no PDF for {} at {:?}", day, self.path(day));
+				return Ok(PdfFetch::Unchanged);
+			}
+			Err(why) => return Err(why.into()),
+		};
+		validate_pdf(None, &body)?;
+
+		let pdf = FetchedPdf::new(day, body, None, None);
+		Ok(self.state.compare_fetched(match self.read_tabula_capture(day).await? {
+			Some(capture) => pdf.with_tabula_capture(capture),
+			None => pdf,
+		}))
+	}
+}
+
+#[async_trait]
+impl ScheduleSource for DirectorySource {
+	/// A missing file means there is no plan for the day, which isn't an error
+	async fn get_weekday_pdf(&self, day: Weekdays) -> Result<PdfFetch, FetchError> {
+		let result = self.read_pdf(day).await;
+
+		self.state.record(day, &result);
+		result
+	}
+
+	fn location(&self, day: Weekdays) -> String {
+		self.path(day).display().to_string()
+	}
+
+	fn state(&self) -> &SourceState {
+		&self.state
+	}
+}
+
+/// Walks through the fixture PDFs in a directory sorted by name, e.g. `sample_plans/`, for demos and tests.
+/// Every day starts at the first fixture and moves on to the next one once the current one is processed,
+/// after the last one nothing changes anymore.
+/// The fixtures are old plans, so they are replayed as the plans of the next date of the day, otherwise they'd be dropped as stale.
+pub struct ReplaySource {
+	fixtures: Vec<PathBuf>,
+	/// The index of the current fixture of every day
+	positions: Mutex<HashMap<Weekdays, usize>>,
+	state: SourceState,
+}
+
+impl ReplaySource {
+	pub fn new(directory: &Path) -> std::io::Result<Self> {
+		let mut fixtures = Vec::new();
+		for entry in std::fs::read_dir(directory)? {
+			let path = entry?.path();
+			if path.is_file() {
+				fixtures.push(path);
+			}
+		}
+		fixtures.sort();
+
+		Ok(Self::from_fixtures(fixtures))
+	}
+
+	pub fn from_fixtures(fixtures: Vec<PathBuf>) -> Self {
+		Self {
+			fixtures,
+			positions: Mutex::new(HashMap::new()),
+			state: SourceState::default(),
+		}
+	}
+
+	fn current_fixture(&self, day: Weekdays) -> Option<&PathBuf> {
+		let position = self.positions.lock().unwrap().get(&day).copied().unwrap_or(0);
+		self.fixtures.get(position)
+	}
+}
+
+#[async_trait]
+impl ScheduleSource for ReplaySource {
+	/// Every fixture counts as changed, even if it's the same as the previous one
+	async fn get_weekday_pdf(&self, day: Weekdays) -> Result<PdfFetch, FetchError> {
+		let result = match self.current_fixture(day) {
+			Some(path) => match tokio::fs::read(path).await {
+				Ok(body) => validate_pdf(None, &body).map(|_| PdfFetch::Changed(FetchedPdf::new(day, body, None, None))),
+				Err(why) => Err(why.into()),
+			},
+			None => Ok(PdfFetch::Unchanged),
+		};
+
+		self.state.record(day, &result);
+		result
+	}
+
+	fn location(&self, day: Weekdays) -> String {
+		self.current_fixture(day).map_or_else(|| "end of the replay".to_owned(), |path| path.display().to_string())
+	}
+
+	fn state(&self) -> &SourceState {
+		&self.state
+	}
+
+	/// Today or the next date on the day
+	fn date_override(&self, day: Weekdays, today: NaiveDate) -> Option<NaiveDate> {
+		(0..7).map(|offset| today + Duration::days(offset))
+			.find(|date| date.weekday().num_days_from_monday() == day as u32)
+	}
+
+	fn mark_processed(&self, pdf: &FetchedPdf) {
+		self.state.mark_processed(pdf);
+		*self.positions.lock().unwrap().entry(pdf.day).or_insert(0) += 1;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use reqwest::StatusCode;
+
+	use super::*;
+
+	fn changed(fetch: PdfFetch) -> FetchedPdf {
+		match fetch {
+			PdfFetch::Changed(pdf) => pdf,
+			PdfFetch::Unchanged => panic!("Expected the PDF to be changed"),
+		}
+	}
+
+	/// A directory in the temp dir that is removed again when dropped
+	struct TempDir(PathBuf);
+
+	impl TempDir {
+		fn new() -> Self {
+			let path = std::env::temp_dir().join(format!("schedule-source-test-{}", util::get_random_name()));
+			std::fs::create_dir_all(&path).unwrap();
+			Self(path)
+		}
+	}
+
+	impl Drop for TempDir {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.0);
+		}
+	}
+
+	#[test]
+	fn test_unchanged_body_is_skipped_once_processed() {
+		let state = SourceState::default();
+		let day = Weekdays::Monday;
+
+		let pdf = changed(state.compare_with_processed(day, b"first".to_vec(), None, None));
+		// Not processed yet, e.g. because the notification failed
+		let pdf = changed(state.compare_with_processed(day, pdf.body, None, None));
+
+		state.mark_processed(&pdf);
+		assert!(matches!(state.compare_with_processed(day, b"first".to_vec(), None, None), PdfFetch::Unchanged));
+		assert!(matches!(state.compare_with_processed(Weekdays::Tuesday, b"first".to_vec(), None, None), PdfFetch::Changed(_)));
+		assert!(matches!(state.compare_with_processed(day, b"second".to_vec(), None, None), PdfFetch::Changed(_)));
+	}
+
+	#[test]
+	fn test_validators_of_unchanged_body_are_kept() {
+		let state = SourceState::default();
+		let day = Weekdays::Friday;
+
+		let pdf = changed(state.compare_with_processed(day, b"plan".to_vec(), None, None));
+		state.mark_processed(&pdf);
+		let _ = state.compare_with_processed(day, b"plan".to_vec(), Some("\"abc\"".to_owned()), None);
+
+		assert_eq!(state.processed_state(day).unwrap().etag, Some("\"abc\"".to_owned()));
+	}
+
+	#[test]
+	fn test_source_health() {
+		let mut health = SourceHealth::default();
+
+		health.record_failure(&FetchError::Status(StatusCode::SERVICE_UNAVAILABLE));
+		health.record_failure(&FetchError::Status(StatusCode::UNAUTHORIZED));
+		assert_eq!(health.consecutive_failures, 2);
+		assert_eq!(health.last_error.as_deref(), Some("Server answered with 401 Unauthorized"));
+		assert!(health.last_success.is_none());
+
+		health.record_success();
+		assert_eq!(health.consecutive_failures, 0);
+		assert!(health.last_success.is_some());
+	}
+
+	#[tokio::test]
+	async fn test_directory_source() {
+		let dir = TempDir::new();
+		let source = DirectorySource::new(&dir.0);
+		let day = Weekdays::Wednesday;
+
+		assert!(matches!(source.get_weekday_pdf(day).await, Ok(PdfFetch::Unchanged)));
+
+		std::fs::write(dir.0.join("Wednesday.pdf"), b"%PDF-1.3 first").unwrap();
+		let pdf = changed(source.get_weekday_pdf(day).await.unwrap());
+		source.mark_processed(&pdf);
+		assert!(matches!(source.get_weekday_pdf(day).await, Ok(PdfFetch::Unchanged)));
+
+		std::fs::write(dir.0.join("Wednesday.pdf"), b"%PDF-1.3 second").unwrap();
+		assert_eq!(changed(source.get_weekday_pdf(day).await.unwrap()).body, b"%PDF-1.3 second");
+
+		std::fs::write(dir.0.join("Wednesday.pdf"), b"<html></html>").unwrap();
+		assert!(matches!(source.get_weekday_pdf(day).await, Err(FetchError::NotAPdf { .. })));
+		assert_eq!(source.health()[0].1.consecutive_failures, 1);
+		assert!(source.location(day).ends_with("Wednesday.pdf"));
+	}
+
+	#[tokio::test]
+	async fn test_replay_source() {
+		let source = ReplaySource::new(Path::new("./sample_plans")).unwrap();
+		let day = Weekdays::Monday;
+
+		let first = changed(source.get_weekday_pdf(day).await.unwrap());
+		// Stays at the fixture until it's processed
+		assert_eq!(changed(source.get_weekday_pdf(day).await.unwrap()).sha256(), first.sha256());
+		source.mark_processed(&first);
+
+		let second = changed(source.get_weekday_pdf(day).await.unwrap());
+		assert_ne!(second.sha256(), first.sha256());
+		// Every day walks through the fixtures on its own
+		assert_eq!(changed(source.get_weekday_pdf(Weekdays::Tuesday).await.unwrap()).sha256(), first.sha256());
+
+		let mut fetch = PdfFetch::Changed(second);
+		while let PdfFetch::Changed(pdf) = fetch {
+			source.mark_processed(&pdf);
+			fetch = source.get_weekday_pdf(day).await.unwrap();
+		}
+		assert_eq!(source.location(day), "end of the replay");
+	}
+}
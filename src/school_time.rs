@@ -0,0 +1,90 @@
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// The timezone of the school, used when none is configured
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Europe::Berlin;
+
+/// The date a plan is for is stored as the milliseconds of its midnight in UTC,
+/// so it's the same date no matter the timezone of the server or the school
+pub fn date_to_millis(date: NaiveDate) -> i64 {
+	date.and_hms(0, 0, 0).timestamp_millis()
+}
+
+/// The date stored with `date_to_millis`
+pub fn millis_to_date(millis: i64) -> NaiveDate {
+	NaiveDateTime::from_timestamp(millis.div_euclid(1000), 0).date()
+}
+
+/// The date at the school at the given time
+pub fn school_date(now: DateTime<Utc>, timezone: Tz) -> NaiveDate {
+	now.with_timezone(&timezone).date().naive_local()
+}
+
+/// The date at the school right now
+pub fn today(timezone: Tz) -> NaiveDate {
+	school_date(Utc::now(), timezone)
+}
+
+/// The time on the clocks of the school right now
+pub fn now(timezone: Tz) -> NaiveDateTime {
+	Utc::now().with_timezone(&timezone).naive_local()
+}
+
+/// Converts a time on the clocks of the school into milliseconds.
+/// A time that happens twice when the clocks go back is the earlier one,
+/// a time that is skipped when the clocks go forward is taken as if they hadn't yet, so 02:30 becomes 03:30.
+pub fn local_to_millis(time: NaiveDateTime, timezone: Tz) -> i64 {
+	match timezone.from_local_datetime(&time) {
+		LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.timestamp_millis(),
+		LocalResult::None => local_to_millis(time + Duration::hours(1), timezone),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+		Utc.ymd(year, month, day).and_hms(hour, minute, 0)
+	}
+
+	#[test]
+	fn test_school_date_on_a_server_running_in_utc() {
+		// 00:30 in Berlin is still the day before in UTC, in winter and in summer
+		assert_eq!(school_date(utc(2021, 1, 10, 23, 30), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 1, 11));
+		assert_eq!(school_date(utc(2021, 6, 13, 22, 30), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 6, 14));
+		assert_eq!(school_date(utc(2021, 6, 13, 21, 59), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 6, 13));
+	}
+
+	#[test]
+	fn test_school_date_around_dst_switches() {
+		// The clocks go forward at 02:00 on 28.03.2021 and back at 03:00 on 31.10.2021
+		assert_eq!(school_date(utc(2021, 3, 27, 22, 59), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 3, 27));
+		assert_eq!(school_date(utc(2021, 3, 27, 23, 0), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 3, 28));
+		assert_eq!(school_date(utc(2021, 10, 30, 21, 59), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 10, 30));
+		assert_eq!(school_date(utc(2021, 10, 30, 22, 0), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 10, 31));
+		assert_eq!(school_date(utc(2021, 10, 31, 22, 59), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 10, 31));
+		assert_eq!(school_date(utc(2021, 10, 31, 23, 0), DEFAULT_TIMEZONE), NaiveDate::from_ymd(2021, 11, 1));
+	}
+
+	#[test]
+	fn test_local_to_millis_around_dst_switches() {
+		let local = |day: u32, month: u32, hour: u32, minute: u32| NaiveDate::from_ymd(2021, month, day).and_hms(hour, minute, 0);
+
+		assert_eq!(local_to_millis(local(18, 2, 7, 35), DEFAULT_TIMEZONE), utc(2021, 2, 18, 6, 35).timestamp_millis());
+		assert_eq!(local_to_millis(local(18, 6, 7, 35), DEFAULT_TIMEZONE), utc(2021, 6, 18, 5, 35).timestamp_millis());
+		// Skipped when the clocks go forward
+		assert_eq!(local_to_millis(local(28, 3, 2, 30), DEFAULT_TIMEZONE), utc(2021, 3, 28, 1, 30).timestamp_millis());
+		// Happens twice when the clocks go back
+		assert_eq!(local_to_millis(local(31, 10, 2, 30), DEFAULT_TIMEZONE), utc(2021, 10, 31, 0, 30).timestamp_millis());
+	}
+
+	#[test]
+	fn test_date_millis_round_trip() {
+		let date = NaiveDate::from_ymd(2021, 3, 28);
+
+		assert_eq!(date_to_millis(date), utc(2021, 3, 28, 0, 0).timestamp_millis());
+		assert_eq!(millis_to_date(date_to_millis(date)), date);
+		assert_eq!(millis_to_date(-1), NaiveDate::from_ymd(1969, 12, 31));
+	}
+}
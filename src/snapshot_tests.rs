@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::school_time;
+use crate::substitution_schedule::SubstitutionSchedule;
+
+/// The directory with the expected output for every fixture
+const SNAPSHOT_DIR: &str = "./snapshots";
+/// Set this environment variable to write the current output as the new expected output
+/// instead of comparing, e.g. `UPDATE_SNAPSHOTS=1 cargo test snapshot`
+const UPDATE_SNAPSHOTS_ENV: &str = "UPDATE_SNAPSHOTS";
+/// The directories containing the fixtures, relative to the repository root
+const FIXTURE_DIRS: [&str; 2] = ["./sample_plans", "./tabula"];
+
+/// The kinds of fixtures we know how to parse
+enum Fixture {
+	Pdf(PathBuf),
+	/// Tabula output, either JSON or CSV
+	Tabula(PathBuf),
+}
+
+impl Fixture {
+	/// Detects the fixture kind from the content, e.g. `tabula/yes3.csv` is actually tabula JSON.
+	/// Tabula CSV starts with the header row. Returns `None` for files we can't parse.
+	fn from_path(path: PathBuf) -> Option<Self> {
+		let content = std::fs::read(&path).ok()?;
+
+		if content.starts_with(b"%PDF") {
+			Some(Fixture::Pdf(path))
+		} else if content.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'[') || content.starts_with(b"Block,") {
+			Some(Fixture::Tabula(path))
+		} else {
+			None
+		}
+	}
+
+	fn path(&self) -> &Path {
+		match self {
+			Fixture::Pdf(path) | Fixture::Tabula(path) => path,
+		}
+	}
+
+	/// The name of the snapshot file, e.g. "sample_plans_1337.json"
+	fn snapshot_path(&self) -> PathBuf {
+		let path = self.path();
+		let dir = path.parent().and_then(Path::file_name).unwrap_or_default().to_string_lossy();
+		let file = path.file_name().unwrap_or_default().to_string_lossy();
+
+		Path::new(SNAPSHOT_DIR).join(format!("{}_{}.json", dir, file))
+	}
+
+	/// Parses the fixture. Errors are part of the snapshot as well, so a fixture that starts or stops failing is caught.
+	fn parse(&self) -> Value {
+		let schedule = match self {
+			Fixture::Pdf(path) => SubstitutionSchedule::from_pdf(path, None, school_time::DEFAULT_TIMEZONE),
+			Fixture::Tabula(path) => {
+				let content = std::fs::read_to_string(path).expect("Couldn't read fixture");
+				// Tabula captures have no date, so we use the epoch
+				SubstitutionSchedule::from_tabula(&content, 0)
+			}
+		};
+
+		match schedule {
+			Ok(schedule) => {
+				let mut value = serde_json::to_value(&schedule).expect("Couldn't serialize the schedule");
+				// The creation time of the struct changes every run
+				if let Some(object) = value.as_object_mut() {
+					object.remove("struct_time");
+				}
+				value
+			}
+			Err(why) => json!({ "error": why.to_string() }),
+		}
+	}
+}
+
+fn fixtures() -> Vec<Fixture> {
+	let mut fixtures = FIXTURE_DIRS.iter()
+		.flat_map(|dir| std::fs::read_dir(dir).expect("Couldn't read fixture directory"))
+		.map(|entry| entry.expect("Couldn't read fixture").path())
+		.filter_map(Fixture::from_path)
+		.collect::<Vec<Fixture>>();
+
+	fixtures.sort_by(|a, b| a.path().cmp(b.path()));
+	fixtures
+}
+
+#[test]
+fn test_snapshots() {
+	let update = std::env::var_os(UPDATE_SNAPSHOTS_ENV).is_some();
+	let mut failures = Vec::new();
+
+	if update {
+		std::fs::create_dir_all(SNAPSHOT_DIR).unwrap();
+	}
+
+	for fixture in fixtures() {
+		let actual = fixture.parse();
+		let snapshot_path = fixture.snapshot_path();
+
+		if update {
+			let json = serde_json::to_string_pretty(&actual).unwrap();
+			std::fs::write(&snapshot_path, format!("{}\n", json)).unwrap();
+			continue;
+		}
+
+		match std::fs::read_to_string(&snapshot_path) {
+			Ok(expected) => {
+				let expected: Value = serde_json::from_str(&expected).expect("Malformed snapshot");
+				if expected != actual {
+					failures.push(format!(
+						"{:?} does not match {:?}, actual output:\n{}",
+						fixture.path(),
+						snapshot_path,
+						serde_json::to_string_pretty(&actual).unwrap()
+					));
+				}
+			}
+			Err(_) => failures.push(format!("Snapshot {:?} for {:?} is missing", snapshot_path, fixture.path())),
+		}
+	}
+
+	assert!(
+		failures.is_empty(),
+		"{}\n\nIf the changes are intended, run the tests with {}=1 to update the snapshots.",
+		failures.join("\n\n"),
+		UPDATE_SNAPSHOTS_ENV
+	);
+}
@@ -1,69 +1,268 @@
-use std::collections::{HashMap, HashSet};
-use std::ffi::OsStr;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::path::Path;
-use std::process::Command;
-use std::str;
 use std::time::SystemTime;
 
-use chrono::{Local, NaiveDate, Offset, Utc};
-use lopdf::Document;
-use serde::{Deserialize, Serialize};
+use chrono::{Datelike, NaiveDate};
+use chrono_tz::Tz;
+use lopdf::{Document, Object};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::error::ParseError;
+use crate::school_time;
+use crate::pdf_table_extractor::{extract_tables, extract_text_outside_tables};
+use crate::schedule_metadata::ScheduleMetadata;
+use crate::tabula_json_parser;
+
+/// What happens to a lesson according to the substitution plan
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SubstitutionKind {
+	/// A different teacher takes over the lesson ("Vertretung", "Aufgabenbetr.")
+	Substitution,
+	/// The lesson does not take place ("----------")
+	Cancellation,
+	/// The lesson takes place in a different room ("Raumverleg.")
+	RoomChange,
+	/// The lesson was moved ("nach Plan", "vorgezogen")
+	Moved,
+	/// Anything we don't recognize, the raw text has to be shown instead
+	Other,
+}
+
+impl Display for SubstitutionKind {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let self_as_string = match self {
+			SubstitutionKind::Substitution => "Substitution",
+			SubstitutionKind::Cancellation => "Cancelled",
+			SubstitutionKind::RoomChange => "Room change",
+			SubstitutionKind::Moved => "Moved",
+			SubstitutionKind::Other => "Other",
+		};
+
+		write!(f, "{}", self_as_string)
+	}
+}
+
+/// A single entry in the substitution table, parsed from the text of the cell(s) of one block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "StoredSubstitution")]
+pub struct Substitution {
+	/// The abbreviation of the teacher, e.g. "FÄN"
+	pub teacher: Option<String>,
+	pub room: Option<String>,
+	pub kind: SubstitutionKind,
+	/// The text as it is in the PDF, kept in case the parsing misses something
+	pub raw: String,
+}
+
+impl Substitution {
+	pub fn parse(raw: &str) -> Self {
+		// Lines in the pdf cells are wrapped at arbitrary points, e.g. "Raumverleg.MÜL\n/ D208"
+		let text = raw.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+		let kind = if text.contains("---") {
+			SubstitutionKind::Cancellation
+		} else if text.contains("Raumverleg") {
+			SubstitutionKind::RoomChange
+		} else if text.contains("Vertretung") || text.contains("Aufgabenbetr") {
+			SubstitutionKind::Substitution
+		} else if text.contains("nach Plan") || text.contains("vorgezogen") {
+			SubstitutionKind::Moved
+		} else {
+			SubstitutionKind::Other
+		};
+
+		let (teacher, room) = match text.split_once('/') {
+			Some((before, after)) => (
+				// The teacher might be glued to the kind, e.g. "Raumverleg.MÜL"
+				before.split_whitespace().last().and_then(|word| word.rsplit('.').next()).map(str::to_owned),
+				after.split_whitespace().next().map(str::to_owned),
+			),
+			None => (
+				text.split_once(" nach Plan").and_then(|(before, _)| before.split_whitespace().last()).map(str::to_owned),
+				None
+			),
+		};
+
+		Self {
+			teacher: teacher.filter(|teacher| is_teacher_abbreviation(teacher)),
+			room: room.filter(|room| !room.is_empty()),
+			kind,
+			raw: raw.to_owned(),
+		}
+	}
+
+	/// Adds a line of text from the cell below and parses the whole text again.
+	pub fn push_line(&mut self, line: &str) {
+		*self = Self::parse(&format!("{}\n{}", self.raw, line));
+	}
+}
+
+/// Teacher abbreviations are short and all uppercase, e.g. "MÜS"
+fn is_teacher_abbreviation(text: &str) -> bool {
+	(2..=4).contains(&text.chars().count()) && text.chars().all(|c| c.is_alphabetic() && c.is_uppercase())
+}
+
+impl From<&str> for Substitution {
+	fn from(raw: &str) -> Self {
+		Self::parse(raw)
+	}
+}
+
+/// Compares the whole text without the whitespace, so a different line wrapping in the PDF is not a change,
+/// but e.g. an added remark is, even if the kind, teacher and room stay the same.
+impl PartialEq for Substitution {
+	fn eq(&self, other: &Self) -> bool {
+		let without_whitespace = |raw: &str| raw.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+
+		self.kind == other.kind
+			&& self.teacher == other.teacher
+			&& self.room == other.room
+			&& without_whitespace(&self.raw) == without_whitespace(&other.raw)
+	}
+}
+
+impl Display for Substitution {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		if self.kind == SubstitutionKind::Other {
+			return write!(f, "{}", self.raw);
+		}
+
+		write!(f, "{}", self.kind)?;
+		match (&self.teacher, &self.room) {
+			(Some(teacher), Some(room)) => write!(f, "\n{} / {}", teacher, room),
+			(Some(teacher), None) => write!(f, "\n{}", teacher),
+			(None, Some(room)) => write!(f, "\n{}", room),
+			(None, None) => Ok(()),
+		}
+	}
+}
+
+/// The stored JSON of a `Substitution`, the fields are always parsed again from the raw text.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredSubstitution {
+	/// Schedules stored before the entries got parsed only contain the text
+	Text(String),
+	Parsed {
+		raw: String,
+	},
+}
+
+impl From<StoredSubstitution> for Substitution {
+	fn from(stored: StoredSubstitution) -> Self {
+		match stored {
+			StoredSubstitution::Text(raw) | StoredSubstitution::Parsed { raw } => Self::parse(&raw),
+		}
+	}
+}
+
+/// The stored JSON of `Substitutions`
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct StoredSubstitutions(BTreeMap<u32, StoredSubstitution>);
 
-use crate::error::StringError;
-use crate::tabula_json_parser::parse;
+impl From<StoredSubstitutions> for Substitutions {
+	/// Schedules stored before the blocks were numbered are keyed by the lesson index, "0" to "5",
+	/// which counted the blocks of the table from the first one. The first block in the plans of the school
+	/// is block 0 ("0:  07:15"), so the old indices already are the block numbers and are kept as they are.
+	fn from(stored: StoredSubstitutions) -> Self {
+		Self {
+			blocks: stored.0.into_iter()
+				.map(|(block, substitution)| (block, substitution.into()))
+				.collect(),
+		}
+	}
+}
 
-/// One column with Substitutions from the PDF
-#[derive(Serialize, Deserialize, PartialOrd, PartialEq, Debug)]
+/// One column with Substitutions from the PDF, the key is the number of the block.
+/// Serialized as a map from the block number to the substitution, e.g. `{ "1": ..., "3": ... }`
+#[derive(Serialize, PartialEq, Debug, Default)]
+#[serde(transparent)]
 pub struct Substitutions {
-	#[serde(rename(serialize = "0"))]
-	#[serde(rename(deserialize = "0"))]
-	#[serde(skip_serializing_if = "Option::is_none")]
-	pub block_0: Option<String>,
-	#[serde(rename(serialize = "1"))]
-	#[serde(rename(deserialize = "1"))]
-	#[serde(skip_serializing_if = "Option::is_none")]
-	pub block_1: Option<String>,
-	#[serde(rename(serialize = "2"))]
-	#[serde(rename(deserialize = "2"))]
-	#[serde(skip_serializing_if = "Option::is_none")]
-	pub block_2: Option<String>,
-	#[serde(rename(serialize = "3"))]
-	#[serde(rename(deserialize = "3"))]
-	#[serde(skip_serializing_if = "Option::is_none")]
-	pub block_3: Option<String>,
-	#[serde(rename(serialize = "4"))]
-	#[serde(rename(deserialize = "4"))]
-	#[serde(skip_serializing_if = "Option::is_none")]
-	pub block_4: Option<String>,
-	#[serde(rename(serialize = "5"))]
-	#[serde(rename(deserialize = "5"))]
-	#[serde(skip_serializing_if = "Option::is_none")]
-	pub block_5: Option<String>,
+	blocks: BTreeMap<u32, Substitution>,
 }
 
 impl Substitutions {
 	pub fn new() -> Self {
 		Self {
-			block_0: None,
-			block_1: None,
-			block_2: None,
-			block_3: None,
-			block_4: None,
-			block_5: None,
+			blocks: BTreeMap::new(),
+		}
+	}
+
+	pub fn get(&self, block: u32) -> Option<&Substitution> {
+		self.blocks.get(&block)
+	}
+
+	/// The blocks with a substitution in order
+	pub fn iter(&self) -> impl Iterator<Item=(u32, &Substitution)> {
+		self.blocks.iter().map(|(block, substitution)| (*block, substitution))
+	}
+
+	pub fn insert(&mut self, block: u32, substitution: Substitution) {
+		self.blocks.insert(block, substitution);
+	}
+
+	/// Adds a line of text to the block, creating the substitution if it doesn't exist yet.
+	pub fn push_line(&mut self, block: u32, line: &str) {
+		match self.blocks.get_mut(&block) {
+			Some(substitution) => substitution.push_line(line),
+			None => self.insert(block, Substitution::parse(line)),
 		}
 	}
-	pub fn first_substitution(&self) -> usize {
-		self.as_array().iter().position(|b| b.is_some()).unwrap_or(0)
+
+	/// Adds the blocks of the other substitutions, e.g. of the same class on another page.
+	/// A block in both with a different substitution is a conflict, the existing substitution is kept
+	/// and the one from `other` is returned with its block.
+	pub fn merge(&mut self, other: Self) -> Vec<(u32, Substitution)> {
+		let mut conflicts = Vec::new();
+
+		for (block, substitution) in other.blocks {
+			match self.blocks.get(&block) {
+				Some(existing) if *existing != substitution => conflicts.push((block, substitution)),
+				Some(_) => {}
+				None => self.insert(block, substitution),
+			}
+		}
+
+		conflicts
 	}
 
-	pub fn last_substitution(&self) -> usize {
-		self.as_array().iter().rposition(|b| b.is_some()).unwrap_or(5)
+	pub fn first_substitution(&self) -> Option<u32> {
+		self.blocks.keys().next().copied()
 	}
 
-	pub fn as_array(&self) -> [&Option<String>; 6] {
-		// One could consider also implementing Iterator
-		[&self.block_0, &self.block_1, &self.block_2, &self.block_3, &self.block_4, &self.block_5]
+	pub fn last_substitution(&self) -> Option<u32> {
+		self.blocks.keys().next_back().copied()
+	}
+}
+
+impl<'de> Deserialize<'de> for Substitutions {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		StoredSubstitutions::deserialize(deserializer).map(Self::from)
+	}
+}
+
+/// The time span of a block, as written in the first column of the table.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct BlockTime {
+	/// e.g. "08:00"
+	pub start: String,
+	/// e.g. "09:30"
+	pub end: String,
+}
+
+impl BlockTime {
+	/// Parses the start of a block, e.g. "1:  08:00" into the block number and the start time.
+	fn parse_start(label: &str) -> Option<(u32, String)> {
+		let (number, start) = label.split_once(':')?;
+		let number = number.trim().parse().ok()?;
+		Some((number, start.trim().to_owned()))
+	}
+
+	/// Parses the end of a block, e.g. "- 09:30" into the end time.
+	fn parse_end(label: &str) -> Option<String> {
+		label.strip_prefix('-').map(|end| end.trim().to_owned())
 	}
 }
 
@@ -73,136 +272,315 @@ impl Display for Substitutions {
 	}
 }
 
+/// A block of a class that is in multiple tables with different substitutions
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MergeConflict {
+	pub class: String,
+	pub block: u32,
+	/// The substitution from the first table, which is used
+	pub kept: Substitution,
+	/// The substitution from the later table
+	pub dropped: Substitution,
+}
+
+/// Comes before the date of the plan, e.g. "Datum: Donnerstag, 28.5.2020"
+const DATE_LABEL: &str = "Datum: ";
+
+/// The German weekday names as they appear in the date line of the PDF, starting with monday
+const WEEKDAY_NAMES: [&str; 7] = ["Montag", "Dienstag", "Mittwoch", "Donnerstag", "Freitag", "Samstag", "Sonntag"];
+
+/// Gets the `CreationDate` from the info dictionary of the PDF, e.g. "D:20200528075053"
+fn pdf_creation_date(document: &Document) -> Option<String> {
+	let info = match document.trailer.get(b"Info").ok()? {
+		Object::Reference(id) => document.get_dictionary(*id).ok()?,
+		Object::Dictionary(dictionary) => dictionary,
+		_ => return None,
+	};
+
+	info.get(b"CreationDate")
+		.and_then(Object::as_str)
+		.ok()
+		.map(|date| String::from_utf8_lossy(date).into_owned())
+}
+
 /// Contains the extracted PDF data of the schedule PDF
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubstitutionSchedule {
-	/// The creation date inside the PDF
+	/// The date the plan is for, see `school_time::date_to_millis`
 	pub pdf_create_date: i64,
 	/// The name of the class is the Key and the Value is a Substitutions struct
 	entries: HashMap<String, Substitutions>,
+	/// The start and end time of every block, the key is the number of the block
+	#[serde(default)]
+	block_times: BTreeMap<u32, BlockTime>,
+	/// The issue time, absent teachers and classes and the notices outside of the tables
+	#[serde(default)]
+	metadata: ScheduleMetadata,
+	/// The blocks of classes in multiple tables that differ between the tables
+	#[serde(default)]
+	conflicts: Vec<MergeConflict>,
 	/// The time when the struct was created, used for comparing the age
 	struct_time: u64,
 }
 
 impl SubstitutionSchedule {
-	#[allow(clippy::ptr_arg)]
-	fn table_to_substitutions(table: &Vec<Vec<String>>) -> HashMap<String, Substitutions> {
+	/// Reads the substitutions of every class in the table and the block times from the first column.
+	/// A block starts at a row like "1:  08:00" and ends with the row like "- 09:30",
+	/// rows in between are continuation lines of the same block.
+	/// Rows after the end of a block belong to it as well, they come from cells that are higher than the block rows.
+	#[allow(clippy::ptr_arg, clippy::type_complexity)]
+	pub fn table_to_substitutions(table_idx: usize, table: &Vec<Vec<String>>) -> Result<(HashMap<String, Substitutions>, BTreeMap<u32, BlockTime>), ParseError> {
 		let mut entries: HashMap<String, Substitutions> = HashMap::new();
+		let mut block_times = BTreeMap::new();
 
-		let classes = &table[0][1..];
+		// A header without classes is fine, e.g. when the school is closed
+		let classes = match table.first() {
+			Some(header) if !header.is_empty() => &header[1..],
+			_ => return Err(ParseError::EmptyTable { table: table_idx }),
+		};
 
 		for class in classes {
 			entries.insert(class.to_string(), Substitutions::new());
 		}
 
-		let mut row = 1;
+		// The block number, its start time and the row it started in
+		let mut current_block: Option<(u32, String, usize)> = None;
+		let mut last_block = None;
+
+		for (row_idx, row) in table.iter().enumerate().skip(1) {
+			if row.len() > table[0].len() {
+				return Err(ParseError::RaggedRow {
+					table: table_idx,
+					row: row_idx,
+					columns: row.len(),
+					expected: table[0].len(),
+				});
+			}
 
-		for lesson_idx in 0..5 {
-			loop {
-				for (i, substitution_part) in table[row][1..].iter().enumerate() {
-					let substitutions = entries.get_mut(&classes[i]).unwrap();
+			let label = row.first().map_or("", String::as_str);
 
-					let block_option = match lesson_idx {
-						0 => &mut substitutions.block_0,
-						1 => &mut substitutions.block_1,
-						2 => &mut substitutions.block_2,
-						3 => &mut substitutions.block_3,
-						4 => &mut substitutions.block_4,
-						5 => &mut substitutions.block_5,
-						_ => panic!("more then 5 lessons used"),
-					};
+			let new_block = match BlockTime::parse_start(label) {
+				Some((block, start)) => Some((block, start)),
+				// A block label we don't understand, count up from the previous block
+				None if current_block.is_none() && !label.is_empty() && !label.starts_with('-') => {
+					Some((last_block.map_or(0, |block: u32| block.saturating_add(1)), label.trim().to_owned()))
+				}
+				None => None,
+			};
+
+			if let Some((block, start)) = new_block {
+				if let Some((unterminated_block, _, start_row)) = current_block {
+					return Err(ParseError::UnterminatedBlock {
+						table: table_idx,
+						row: start_row,
+						block: unterminated_block,
+					});
+				}
+				current_block = Some((block, start, row_idx));
+			}
 
+			if let Some(block) = current_block.as_ref().map(|(block, _, _)| block).or(last_block.as_ref()) {
+				for (class, substitution_part) in classes.iter().zip(row.iter().skip(1)) {
 					if !substitution_part.is_empty() {
-						if let Some(block) = block_option {
-							block.push_str(&format!("\n{}", substitution_part.clone()));
-						} else {
-							let _ = block_option.insert(substitution_part.clone());
+						if let Some(substitutions) = entries.get_mut(class) {
+							substitutions.push_line(*block, substitution_part);
 						}
 					}
 				}
+			}
 
-				if table[row][0].starts_with('-') {
-					break;
+			if let Some(end) = BlockTime::parse_end(label) {
+				if let Some((block, start, _)) = current_block.take() {
+					block_times.insert(block, BlockTime { start, end });
+					last_block = Some(block);
 				}
-				row += 1;
 			}
+		}
 
-			row += 1;
+		if let Some((block, _, start_row)) = current_block {
+			return Err(ParseError::UnterminatedBlock {
+				table: table_idx,
+				row: start_row,
+				block,
+			});
 		}
 
-		entries
+		Ok((entries, block_times))
 	}
 
 	#[allow(clippy::ptr_arg)]
-	pub fn from_table(tables: &Vec<Vec<Vec<String>>>, pdf_create_date: i64) -> Self {
-		let mut entries = HashMap::new();
+	pub fn from_table(tables: &Vec<Vec<Vec<String>>>, pdf_create_date: i64) -> Result<Self, ParseError> {
+		if tables.is_empty() {
+			return Err(ParseError::NoTables);
+		}
 
-		for table in tables {
-			entries.extend(Self::table_to_substitutions(table));
+		let mut entries: HashMap<String, Substitutions> = HashMap::new();
+		let mut block_times = BTreeMap::new();
+		let mut conflicts = Vec::new();
+
+		for (table_idx, table) in tables.iter().enumerate() {
+			let (table_entries, table_block_times) = Self::table_to_substitutions(table_idx, table)?;
+
+			// A class can be in multiple tables, e.g. on every page, so the tables are merged block by block
+			for (class, substitutions) in table_entries {
+				let existing = match entries.get_mut(&class) {
+					Some(existing) => existing,
+					None => {
+						entries.insert(class, substitutions);
+						continue;
+					}
+				};
+
+				for (block, dropped) in existing.merge(substitutions) {
+					// The unwrap is safe since the conflict is with an existing block
+					let kept = existing.get(block).unwrap().clone();
+					log::warn!("Class {} block {} differs in table {}: kept {:?}, dropped {:?}", class, block, table_idx, kept.raw, dropped.raw);
+					conflicts.push(MergeConflict {
+						class: class.clone(),
+						block,
+						kept,
+						dropped,
+					});
+				}
+			}
+
+			for (block, time) in table_block_times {
+				match block_times.get(&block) {
+					Some(existing) if *existing != time => log::warn!("Block {} has different times in table {}: {:?} and {:?}", block, table_idx, existing, time),
+					Some(_) => {}
+					None => {
+						block_times.insert(block, time);
+					}
+				}
+			}
 		}
 
-		let time_now = SystemTime::now();
-		let since_the_epoch = time_now
+		let since_the_epoch = SystemTime::now()
 			.duration_since(SystemTime::UNIX_EPOCH)
-			.expect("Time got fucked");
+			.unwrap_or_default();
 
 		#[allow(clippy::cast_possible_truncation)]
 			let time_millis = since_the_epoch.as_millis() as u64;
 
-		Self {
+		Ok(Self {
 			pdf_create_date,
 			entries,
+			block_times,
+			metadata: ScheduleMetadata::default(),
+			conflicts,
 			struct_time: time_millis,
-		}
+		})
+	}
+
+	/// Builds the schedule from tabula output in either JSON or CSV format,
+	/// e.g. a capture from a manual tabula run or a hand-fixed extraction.
+	pub fn from_tabula(content: &str, pdf_create_date: i64) -> Result<Self, ParseError> {
+		Self::from_table(&tabula_json_parser::parse_any(content)?, pdf_create_date)
 	}
 
-	pub fn from_pdf<T: AsRef<Path> + AsRef<OsStr>>(path: T) -> Result<Self, Box<dyn std::error::Error>> {
-		// let pdf = Document::load(&path).map_err(|_| return Err(StringError::new("PDF is empty or malformed.")))?;
-		let pdf = match Document::load(&path) {
-			Ok(pdf) => pdf,
-			Err(_) => return Err(Box::new(StringError::new("PDF is empty or malformed."))),
+	/// The times in the PDF, e.g. the issue time, are read in the timezone of the school.
+	/// The tables are read from the tabula capture instead if there is one, e.g. a hand-fixed extraction,
+	/// the date and the text outside of the tables are still read from the PDF.
+	pub fn from_pdf<T: AsRef<Path>>(path: T, tabula_capture: Option<&str>, timezone: Tz) -> Result<Self, ParseError> {
+		let document = Document::load(&path).map_err(|why| ParseError::MalformedPdf(why.to_string()))?;
+
+		let pages = document.get_pages().keys()
+			.map(|page| document.extract_text(&[*page]).map(|text| (*page, text)))
+			.collect::<Result<Vec<(u32, String)>, _>>()
+			.map_err(|why| ParseError::MalformedPdf(why.to_string()))?;
+		let date = Self::parse_page_dates(&pages)?;
+
+		let mut schedule = match tabula_capture {
+			Some(capture) => Self::from_tabula(capture, date)?,
+			None => Self::from_table(&extract_tables(&document)?, date)?,
 		};
-		let pdf = pdf.extract_text(&[1])?;
+		schedule.metadata = ScheduleMetadata::parse(&extract_text_outside_tables(&document)?, pdf_creation_date(&document).as_deref(), timezone);
 
-		let date_idx_start = pdf.find("Datum: ").ok_or("date not found")?;
-		let date_idx_end = pdf[date_idx_start..].find('\n').ok_or("date end not found")? + date_idx_start;
+		Ok(schedule)
+	}
 
-		let date_str: Vec<u32> = pdf[date_idx_start..date_idx_end].split(", ")
-			.last()
-			.ok_or("date string has no ','")?
-			.split('.')
-			.collect::<Vec<&str>>()
-			.iter()
-			.map(|s| (*s).parse::<u32>().unwrap())
-			.collect();
+	/// Finds the date in the text of the pages, the first page has to have it.
+	/// Later pages don't need to repeat it, e.g. a continuation page without the header, but if they do it has to be the same day.
+	fn parse_page_dates(pages: &[(u32, String)]) -> Result<i64, ParseError> {
+		let (_, first_page) = pages.first().ok_or(ParseError::MissingDate)?;
+		let date = Self::parse_date(first_page)?;
 
-		#[allow(clippy::cast_possible_wrap)]
-			let date = chrono::Date::<Local>::from_utc(
-			NaiveDate::from_ymd(date_str[2] as i32, date_str[1], date_str[0]),
-			Utc.fix(),
-		).and_hms_milli(0, 0, 0, 0).timestamp_millis();
+		for (page, text) in &pages[1..] {
+			if !text.contains(DATE_LABEL) {
+				continue;
+			}
 
+			let page_date = Self::parse_date(text)?;
+			if page_date != date {
+				return Err(ParseError::InconsistentDate {
+					page: *page,
+					date,
+					page_date,
+				});
+			}
+		}
+
+		Ok(date)
+	}
 
-		let output = Command::new("java")
-			.arg("-jar")
-			.arg("./tabula/tabula.jar")
-			.arg("-g")
-			.arg("-f")
-			.arg("JSON")
-			.arg("-p")
-			.arg("all")
-			.arg(path)
-			.output()?;
+	/// Finds the date in the text of the PDF, e.g. "Datum: Donnerstag, 28.5.2020" and returns it as a timestamp in milliseconds.
+	/// If there is a weekday it has to match the date, otherwise the date is most likely wrong.
+	fn parse_date(pdf_text: &str) -> Result<i64, ParseError> {
+		let date_idx_start = pdf_text.find(DATE_LABEL).ok_or(ParseError::MissingDate)?;
+		let date_line = pdf_text[date_idx_start..].lines().next().unwrap_or_default();
 
-		let table = parse(str::from_utf8(&output.stdout).unwrap())?;
+		let bad_date = || ParseError::BadDate(date_line.to_owned());
+
+		// The weekday is between "Datum: " and the ", " before the date
+		let (weekday, date_text) = match date_line[DATE_LABEL.len()..].split_once(", ") {
+			Some((weekday, date_text)) => (Some(weekday.trim()), date_text),
+			None => (None, &date_line[DATE_LABEL.len()..]),
+		};
 
-		Ok(Self::from_table(&table, date))
+		let date_parts = date_text
+			.trim()
+			.split('.')
+			.map(|s| s.parse::<u32>().map_err(|_| bad_date()))
+			.collect::<Result<Vec<u32>, ParseError>>()?;
+
+		let (day, month, year) = match date_parts[..] {
+			[day, month, year] => (day, month, year),
+			_ => return Err(bad_date()),
+		};
+
+		#[allow(clippy::cast_possible_wrap)]
+			let naive_date = NaiveDate::from_ymd_opt(year as i32, month, day).ok_or_else(bad_date)?;
+
+		match weekday {
+			Some(weekday) if WEEKDAY_NAMES[naive_date.weekday().num_days_from_monday() as usize] != weekday => {
+				return Err(ParseError::WeekdayMismatch {
+					weekday: weekday.to_owned(),
+					date: naive_date.format("%d.%m.%Y").to_string(),
+				});
+			}
+			_ => {}
+		}
+
+		Ok(school_time::date_to_millis(naive_date))
 	}
 
 	pub fn get_substitutions(&self, class: &str) -> Option<&Substitutions> {
 		self.entries.get(class)
 	}
 
+	pub fn get_block_times(&self) -> &BTreeMap<u32, BlockTime> {
+		&self.block_times
+	}
+
+	pub fn get_metadata(&self) -> &ScheduleMetadata {
+		&self.metadata
+	}
+
+	/// The date the plan is for
+	pub fn get_date(&self) -> NaiveDate {
+		school_time::millis_to_date(self.pdf_create_date)
+	}
+
 	pub fn _get_entries(&self) -> &HashMap<String, Substitutions> { &self.entries }
 
 	pub fn get_classes(&self) -> HashSet<String> {
@@ -234,4 +612,301 @@ impl Display for SubstitutionSchedule {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		write!(f, "{}", serde_json::to_string_pretty(self).unwrap())
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_substitution() {
+		let substitution = Substitution::parse("FÄN / F018\nVertretung");
+		assert_eq!(substitution.kind, SubstitutionKind::Substitution);
+		assert_eq!(substitution.teacher.as_deref(), Some("FÄN"));
+		assert_eq!(substitution.room.as_deref(), Some("F018"));
+		assert_eq!(substitution.raw, "FÄN / F018\nVertretung");
+	}
+
+	#[test]
+	fn test_parse_cancellation() {
+		let substitution = Substitution::parse("KRA nach Plan\n----------");
+		assert_eq!(substitution.kind, SubstitutionKind::Cancellation);
+		assert_eq!(substitution.teacher.as_deref(), Some("KRA"));
+		assert_eq!(substitution.room, None);
+	}
+
+	#[test]
+	fn test_parse_room_change_with_wrapped_line() {
+		let substitution = Substitution::parse("Raumverleg.MÜL\n/ D208");
+		assert_eq!(substitution.kind, SubstitutionKind::RoomChange);
+		assert_eq!(substitution.teacher.as_deref(), Some("MÜL"));
+		assert_eq!(substitution.room.as_deref(), Some("D208"));
+	}
+
+	#[test]
+	fn test_parse_moved_and_other() {
+		assert_eq!(Substitution::parse("nach Plan").kind, SubstitutionKind::Moved);
+		assert_eq!(Substitution::parse("vorgezogen").kind, SubstitutionKind::Moved);
+
+		let other = Substitution::parse("Exkursion");
+		assert_eq!(other.kind, SubstitutionKind::Other);
+		assert_eq!(other.to_string(), "Exkursion");
+	}
+
+	#[test]
+	fn test_substitution_equality_ignores_line_wrapping() {
+		assert_eq!(Substitution::parse("Raumverleg.MÜL\n/ D208"), Substitution::parse("Raumverleg. MÜL / D208"));
+		assert_ne!(Substitution::parse("FÄN / F018\nVertretung"), Substitution::parse("FÄN / F019\nVertretung"));
+	}
+
+	#[test]
+	fn test_substitution_equality_compares_the_whole_text() {
+		// Same kind, teacher and room
+		assert_ne!(Substitution::parse("FÄN / F018\nVertretung"), Substitution::parse("FÄN / F018\nVertretung\nAufgaben in Moodle"));
+		assert_ne!(Substitution::parse("Vertretung\nFÄN / F018"), Substitution::parse("Aufgabenbetr.\nFÄN / F018"));
+		// Not a teacher abbreviation, the teacher is None on both sides
+		assert_ne!(Substitution::parse("Müller / F018\nVertretung"), Substitution::parse("Schmidt / F018\nVertretung"));
+	}
+
+	#[test]
+	fn test_deserialize_text_only_substitutions() {
+		// Written by the parser from before the blocks were numbered (the baseline revision) from the tabula capture
+		// of the same plan, so the struct_time is when the fixture was generated, not when the plan was published
+		let legacy: SubstitutionSchedule = serde_json::from_str(&std::fs::read_to_string("./legacy_data/pdf_jsons/Thursday.json").unwrap()).unwrap();
+		let current = SubstitutionSchedule::from_tabula(&std::fs::read_to_string("./tabula/1337.json").unwrap(), 0).unwrap();
+
+		assert_eq!(legacy.get_classes(), current.get_classes());
+		for class in current.get_classes() {
+			assert_eq!(legacy.get_substitutions(&class), current.get_substitutions(&class), "{}", class);
+		}
+		assert_eq!(legacy.get_substitutions("TSP171").unwrap().get(1), Some(&Substitution::parse("FÄN / F018\nVertretung")));
+		assert_eq!(legacy.get_substitutions("TSE181").unwrap().get(4).unwrap().kind, SubstitutionKind::Cancellation);
+		// The lesson index 0 is block 0
+		let first_block: Substitutions = serde_json::from_str(r#"{ "0": "nach Plan" }"#).unwrap();
+		assert_eq!(first_block.get(0).unwrap().kind, SubstitutionKind::Moved);
+	}
+
+	fn table(rows: &[&[&str]]) -> Vec<Vec<String>> {
+		rows.iter().map(|row| row.iter().map(|cell| (*cell).to_owned()).collect()).collect()
+	}
+
+	#[test]
+	fn test_table_to_substitutions() {
+		let table = table(&[
+			&["Block", "TSP171", "BGYM191"],
+			&["1:  08:00", "FÄN / F018", ""],
+			&["- 09:30", "Vertretung", "nach Plan"],
+			&["2:  09:50", "", "KLE / G203"],
+			&["", "nach Plan", ""],
+			&["- 11:20", "", "Vertretung"],
+		]);
+
+		let (entries, block_times) = SubstitutionSchedule::table_to_substitutions(0, &table).unwrap();
+
+		assert_eq!(entries["TSP171"].get(1), Some(&Substitution::parse("FÄN / F018\nVertretung")));
+		assert_eq!(entries["TSP171"].get(2), Some(&Substitution::parse("nach Plan")));
+		assert_eq!(entries["BGYM191"].get(1), Some(&Substitution::parse("nach Plan")));
+		assert_eq!(entries["BGYM191"].get(2), Some(&Substitution::parse("KLE / G203\nVertretung")));
+		assert_eq!(block_times[&1], BlockTime { start: "08:00".to_owned(), end: "09:30".to_owned() });
+		assert_eq!(block_times[&2], BlockTime { start: "09:50".to_owned(), end: "11:20".to_owned() });
+	}
+
+	#[test]
+	fn test_table_to_substitutions_overflowing_cell() {
+		// The cell is higher than the two rows of its block, the last line ends up below the end of the block
+		let table = table(&[
+			&["Block", "BGYM192"],
+			&["3:  11:40", "SHM / D215"],
+			&["- 13:10", "Raumverleg.SHM"],
+			&["", "Vertretung"],
+			&["4:  13:30", ""],
+			&["- 15:00", "nach Plan"],
+		]);
+
+		let (entries, _) = SubstitutionSchedule::table_to_substitutions(0, &table).unwrap();
+
+		assert_eq!(entries["BGYM192"].get(3).unwrap().raw, "SHM / D215\nRaumverleg.SHM\nVertretung");
+		assert_eq!(entries["BGYM192"].get(4), Some(&Substitution::parse("nach Plan")));
+	}
+
+	#[test]
+	fn test_table_to_substitutions_additional_block() {
+		let table = table(&[
+			&["Block", "TSP171"],
+			&["5:  15:15", ""],
+			&["- 16:45", ""],
+			&["6:  17:00", "ERE / F019"],
+			&["- 18:30", "Vertretung"],
+		]);
+
+		let (entries, block_times) = SubstitutionSchedule::table_to_substitutions(0, &table).unwrap();
+
+		assert_eq!(entries["TSP171"].get(6), Some(&Substitution::parse("ERE / F019\nVertretung")));
+		assert_eq!(entries["TSP171"].first_substitution(), Some(6));
+		assert_eq!(block_times.keys().copied().collect::<Vec<u32>>(), vec![5, 6]);
+		assert_eq!(block_times[&6], BlockTime { start: "17:00".to_owned(), end: "18:30".to_owned() });
+	}
+
+	#[test]
+	fn test_table_to_substitutions_errors() {
+		assert_eq!(SubstitutionSchedule::table_to_substitutions(2, &Vec::new()), Err(ParseError::EmptyTable { table: 2 }));
+
+		let unterminated = table(&[
+			&["Block", "TSP171"],
+			&["1:  08:00", "FÄN / F018"],
+			&["2:  09:50", ""],
+			&["- 11:20", ""],
+		]);
+		assert_eq!(
+			SubstitutionSchedule::table_to_substitutions(0, &unterminated),
+			Err(ParseError::UnterminatedBlock { table: 0, row: 1, block: 1 })
+		);
+
+		let never_ended = table(&[&["Block", "TSP171"], &["1:  08:00", "FÄN / F018"]]);
+		assert_eq!(
+			SubstitutionSchedule::table_to_substitutions(0, &never_ended),
+			Err(ParseError::UnterminatedBlock { table: 0, row: 1, block: 1 })
+		);
+
+		let ragged = table(&[&["Block", "TSP171"], &["1:  08:00", "FÄN / F018", "nach Plan"], &["- 09:30", ""]]);
+		assert_eq!(
+			SubstitutionSchedule::table_to_substitutions(1, &ragged),
+			Err(ParseError::RaggedRow { table: 1, row: 1, columns: 3, expected: 2 })
+		);
+
+		assert_eq!(SubstitutionSchedule::from_table(&Vec::new(), 0).unwrap_err(), ParseError::NoTables);
+	}
+
+	#[test]
+	fn test_from_table_merges_classes() {
+		let first = table(&[
+			&["Block", "TSP171", "BGYM191"],
+			&["1:  08:00", "FÄN / F018", "nach Plan"],
+			&["- 09:30", "Vertretung", ""],
+		]);
+		let second = table(&[
+			&["Block", "TSP171", "IM171"],
+			&["1:  08:00", "FÄN / F018", ""],
+			&["- 09:30", "Vertretung", "---"],
+			&["2:  09:50", "nach Plan", ""],
+			&["- 11:20", "", ""],
+		]);
+		let conflicting = table(&[
+			&["Block", "BGYM191"],
+			&["1:  08:00", "KLE / G203"],
+			&["- 09:30", "Vertretung"],
+		]);
+
+		let schedule = SubstitutionSchedule::from_table(&vec![first, second, conflicting], 0).unwrap();
+
+		let tsp171 = schedule.get_substitutions("TSP171").unwrap();
+		assert_eq!(tsp171.get(1), Some(&Substitution::parse("FÄN / F018\nVertretung")));
+		assert_eq!(tsp171.get(2), Some(&Substitution::parse("nach Plan")));
+		assert_eq!(schedule.get_substitutions("IM171").unwrap().get(1).unwrap().kind, SubstitutionKind::Cancellation);
+		assert_eq!(schedule.get_substitutions("BGYM191").unwrap().get(1), Some(&Substitution::parse("nach Plan")));
+		assert_eq!(schedule.get_block_times().keys().copied().collect::<Vec<u32>>(), vec![1, 2]);
+
+		assert_eq!(schedule.conflicts, vec![MergeConflict {
+			class: "BGYM191".to_owned(),
+			block: 1,
+			kept: Substitution::parse("nach Plan"),
+			dropped: Substitution::parse("KLE / G203\nVertretung"),
+		}]);
+	}
+
+	#[test]
+	fn test_parse_date() {
+		let text = "Stundenplan-Änderungen\nDatum: Donnerstag, 28.5.2020\nBlock\n";
+		let expected = NaiveDate::from_ymd(2020, 5, 28).and_hms(0, 0, 0).timestamp_millis();
+		assert_eq!(SubstitutionSchedule::parse_date(text), Ok(expected));
+
+		assert_eq!(SubstitutionSchedule::parse_date("Block\n"), Err(ParseError::MissingDate));
+		assert_eq!(
+			SubstitutionSchedule::parse_date("Datum: Donnerstag, 28.5.\n"),
+			Err(ParseError::BadDate("Datum: Donnerstag, 28.5.".to_owned()))
+		);
+		assert_eq!(
+			SubstitutionSchedule::parse_date("Datum: Donnerstag, 31.2.2020\n"),
+			Err(ParseError::BadDate("Datum: Donnerstag, 31.2.2020".to_owned()))
+		);
+		assert_eq!(
+			SubstitutionSchedule::parse_date("Datum: Freitag, 28.5.2020\n"),
+			Err(ParseError::WeekdayMismatch { weekday: "Freitag".to_owned(), date: "28.05.2020".to_owned() })
+		);
+		// Without the weekday there is nothing to check it against
+		assert_eq!(SubstitutionSchedule::parse_date("Datum: 28.5.2020\n"), Ok(expected));
+	}
+
+	#[test]
+	fn test_parse_page_dates() {
+		let expected = NaiveDate::from_ymd(2020, 5, 28).and_hms(0, 0, 0).timestamp_millis();
+		let page = |page: u32, text: &str| (page, text.to_owned());
+
+		// A continuation page without the header
+		assert_eq!(
+			SubstitutionSchedule::parse_page_dates(&[page(1, "Datum: Donnerstag, 28.5.2020\nBlock\n"), page(2, "Block\n")]),
+			Ok(expected)
+		);
+		assert_eq!(
+			SubstitutionSchedule::parse_page_dates(&[page(1, "Datum: Donnerstag, 28.5.2020\n"), page(2, "Datum: 28.5.2020\n")]),
+			Ok(expected)
+		);
+		assert_eq!(
+			SubstitutionSchedule::parse_page_dates(&[page(1, "Datum: Donnerstag, 28.5.2020\n"), page(2, "Datum: Freitag, 29.5.2020\n")]),
+			Err(ParseError::InconsistentDate { page: 2, date: expected, page_date: expected + 86_400_000 })
+		);
+		// Only the first page has to have the date
+		assert_eq!(
+			SubstitutionSchedule::parse_page_dates(&[page(1, "Block\n"), page(2, "Datum: Donnerstag, 28.5.2020\n")]),
+			Err(ParseError::MissingDate)
+		);
+		assert_eq!(SubstitutionSchedule::parse_page_dates(&[]), Err(ParseError::MissingDate));
+	}
+
+	#[test]
+	fn test_from_pdf_metadata() {
+		let schedule = SubstitutionSchedule::from_pdf("./sample_plans/86111", None, school_time::DEFAULT_TIMEZONE).unwrap();
+		let metadata = schedule.get_metadata();
+
+		// The creation date of the PDF is 15:06:31 in Berlin
+		assert_eq!(metadata.issued_at, Some(NaiveDate::from_ymd(2020, 2, 17).and_hms(14, 6, 31).timestamp_millis()));
+		assert_eq!(metadata.absent_classes, vec!["TSE191".to_owned(), "MB191".to_owned()]);
+		assert_eq!(metadata.notices, vec!["Die AzuBis der Klassen TSE 191 und MB191 sind in die Betriebe bestellt.".to_owned()]);
+	}
+
+	#[test]
+	fn test_from_pdf_with_tabula_capture() {
+		// A hand-fixed room in the capture of the tables of the PDF
+		let capture = std::fs::read_to_string("./tabula/1337.json").unwrap().replace("F019", "F020");
+		let schedule = SubstitutionSchedule::from_pdf("./tabula/1337", Some(&capture), school_time::DEFAULT_TIMEZONE).unwrap();
+
+		assert_eq!(schedule.get_substitutions("TSE181").unwrap().get(2).unwrap().room.as_deref(), Some("F020"));
+		assert_eq!(schedule.get_date(), SubstitutionSchedule::from_pdf("./tabula/1337", None, school_time::DEFAULT_TIMEZONE).unwrap().get_date());
+	}
+
+	#[test]
+	fn test_from_pdf_malformed() {
+		let path = format!("/tmp/test-{}", crate::util::get_random_name());
+		std::fs::write(&path, "not a pdf").unwrap();
+
+		assert!(matches!(SubstitutionSchedule::from_pdf(&path, None, school_time::DEFAULT_TIMEZONE), Err(ParseError::MalformedPdf(_))));
+	}
+
+	#[test]
+	fn test_deserialize_schedule_without_block_times() {
+		let json = r#"{ "pdf_create_date": 0, "entries": { "TSP171": { "1": "nach Plan" } }, "struct_time": 0 }"#;
+		let schedule: SubstitutionSchedule = serde_json::from_str(json).unwrap();
+
+		assert!(schedule.get_block_times().is_empty());
+		assert_eq!(schedule.get_substitutions("TSP171").unwrap().get(1).unwrap().kind, SubstitutionKind::Moved);
+	}
+
+	#[test]
+	fn test_serde_round_trip() {
+		let mut substitutions = Substitutions::new();
+		substitutions.insert(2, Substitution::parse("ERE / F019\nVertretung"));
+
+		let json = serde_json::to_string(&substitutions).unwrap();
+		assert_eq!(substitutions, serde_json::from_str(&json).unwrap());
+	}
+}
@@ -4,19 +4,73 @@ use std::fmt::Formatter;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::error::ParseError;
+
+/// The first cell of the header row of every table
+const TABLE_HEADER_FIRST_CELL: &str = "Block";
+
+/// Extracts the text from the rows and cells in either format tabula outputs, JSON or CSV.
+/// JSON is detected by the leading '[', everything else is parsed as CSV.
+pub fn parse_any(content: &str) -> Result<Vec<Vec<Vec<String>>>, ParseError> {
+	if content.trim_start().starts_with('[') {
+		parse(content)
+	} else {
+		parse_csv(content)
+	}
+}
+
+/// Extracts the text from the rows and cells in the csv that gets outputted by tabula.
+/// Tabula writes all tables one after another, so a new table starts at every header row.
+/// Cells spanning multiple lines are quoted and keep their line breaks.
+pub fn parse_csv(content: &str) -> Result<Vec<Vec<Vec<String>>>, ParseError> {
+	let mut reader = csv::ReaderBuilder::new()
+		.has_headers(false)
+		.flexible(true)
+		.from_reader(content.as_bytes());
+
+	let mut tables: Vec<Vec<Vec<String>>> = Vec::new();
+	for (row_idx, record) in reader.records().enumerate() {
+		let record = record.map_err(|why| ParseError::MalformedCsv {
+			row: Some(row_idx),
+			message: why.to_string(),
+		})?;
+		let row = record.iter().map(str::to_owned).collect::<Vec<String>>();
+
+		if row.first().map(String::as_str) == Some(TABLE_HEADER_FIRST_CELL) {
+			tables.push(vec![row]);
+		} else if let Some(table) = tables.last_mut() {
+			table.push(row);
+		} else {
+			return Err(ParseError::MalformedCsv {
+				row: Some(row_idx),
+				message: format!("Row before the first header row starting with '{}'", TABLE_HEADER_FIRST_CELL),
+			});
+		}
+	}
+
+	Ok(tables)
+}
+
 /// Extracts the text from the rows and cells in the json that gets outputted by tabula
-pub fn parse(content: &str) -> Result<Vec<Vec<Vec<String>>>, Box<dyn std::error::Error>> {
-	let json: Value = serde_json::from_str(content)?;
-	let array = json.as_array().ok_or("Json malformed")?;
+pub fn parse(content: &str) -> Result<Vec<Vec<Vec<String>>>, ParseError> {
+	let malformed = |table: Option<usize>, row: Option<usize>, message: &str| ParseError::MalformedJson {
+		table,
+		row,
+		message: message.to_owned(),
+	};
+
+	let json: Value = serde_json::from_str(content).map_err(|why| malformed(None, None, &why.to_string()))?;
+	let array = json.as_array().ok_or_else(|| malformed(None, None, "Json malformed"))?;
 
 	let mut tables = Vec::new();
-	for entry in array {
-		let object = entry.as_object().ok_or("Json malformed")?;
-		let data = object.get("data").ok_or("Json data field missing")?;
+	for (table_idx, entry) in array.iter().enumerate() {
+		let object = entry.as_object().ok_or_else(|| malformed(Some(table_idx), None, "Json malformed"))?;
+		let data = object.get("data").ok_or_else(|| malformed(Some(table_idx), None, "Json data field missing"))?;
 
 		let mut table_rows = Vec::new();
-		for row in data.as_array().ok_or("Json data missing")? {
-			let row: Vec<Cell> = serde_json::from_value(row.clone())?;
+		for (row_idx, row) in data.as_array().ok_or_else(|| malformed(Some(table_idx), None, "Json data missing"))?.iter().enumerate() {
+			let row: Vec<Cell> = serde_json::from_value(row.clone())
+				.map_err(|why| malformed(Some(table_idx), Some(row_idx), &why.to_string()))?;
 			let row = Row {
 				row
 			};
@@ -27,16 +81,65 @@ pub fn parse(content: &str) -> Result<Vec<Vec<Vec<String>>>, Box<dyn std::error:
 
 	let mut tables_with_rows_as_text = Vec::new();
 	for table_rows in tables {
-		let mut rows_as_text = Vec::new();
-		for mut row in table_rows {
-			rows_as_text.push(row.extract_text());
-		}
-		tables_with_rows_as_text.push(rows_as_text);
+		tables_with_rows_as_text.push(assign_cells(table_rows));
 	}
 
 	Ok(tables_with_rows_as_text)
 }
 
+/// Puts the text of every cell into the row and column it belongs to, using the position of the cells
+/// instead of their position in the tabula output, which is off when tabula merges or splits columns.
+/// The columns are given by the header row, cells belong to the column with the closest header.
+/// Cells whose vertical extents overlap are put in the same row.
+/// Falls back to the position in the tabula output if the header has no geometry.
+fn assign_cells(mut rows: Vec<Row>) -> Vec<Vec<String>> {
+	if rows.is_empty() {
+		return Vec::new();
+	}
+
+	let mut header = rows.remove(0).row
+		.into_iter()
+		.filter(|cell| !cell.is_empty())
+		.collect::<Vec<Cell>>();
+
+	if header.is_empty() || header.iter().any(|cell| cell.width <= 0.0) {
+		let mut table = vec![header.iter().map(|cell| cell.text.clone()).collect::<Vec<String>>()];
+		table.extend(rows.iter_mut().map(Row::extract_text));
+		return table;
+	}
+
+	header.sort_by(|a, b| a.left.partial_cmp(&b.left).unwrap_or(std::cmp::Ordering::Equal));
+	// The border between two columns is in the middle between their headers
+	let borders = header.windows(2)
+		.map(|pair| (pair[0].center_x() + pair[1].center_x()) / 2.0)
+		.collect::<Vec<f64>>();
+
+	let mut cells = rows.into_iter()
+		.flat_map(|row| row.row)
+		.filter(|cell| !cell.is_empty())
+		.collect::<Vec<Cell>>();
+	cells.sort_by(|a, b| a.top.partial_cmp(&b.top).unwrap_or(std::cmp::Ordering::Equal));
+
+	let mut table = vec![header.iter().map(|cell| cell.text.trim().to_owned()).collect::<Vec<String>>()];
+	let mut row_bottom = f64::NEG_INFINITY;
+	for cell in cells {
+		if cell.top >= row_bottom {
+			table.push(vec![String::new(); header.len()]);
+		}
+		row_bottom = row_bottom.max(cell.bottom());
+
+		let column = borders.iter().take_while(|border| cell.center_x() >= **border).count();
+		// The unwrap is safe since a row was pushed before if there was none
+		let text = &mut table.last_mut().unwrap()[column];
+		if !text.is_empty() {
+			text.push(' ');
+		}
+		text.push_str(cell.text.trim());
+	}
+
+	table
+}
+
 /// A row in the substitution table
 #[derive(Debug, Deserialize, Serialize)]
 struct Row {
@@ -65,8 +168,131 @@ struct Cell {
 	text: String,
 }
 
+impl Cell {
+	/// Tabula outputs empty cells (with all positions 0) to fill up the rows
+	fn is_empty(&self) -> bool {
+		self.text.trim().is_empty()
+	}
+
+	fn center_x(&self) -> f64 {
+		self.left + self.width / 2.0
+	}
+
+	fn bottom(&self) -> f64 {
+		self.top + self.height
+	}
+}
+
 impl Display for Cell {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		write!(f, "{}", self.text)
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_csv() {
+		let csv = "Block,TSP171,TSE181\n1:  08:00,\"FÄN / F018\nVertretung\",\n- 09:30,,nach Plan\n\"\",\"\",\n";
+
+		let tables = parse_csv(csv).unwrap();
+
+		assert_eq!(tables, vec![vec![
+			vec!["Block".to_owned(), "TSP171".to_owned(), "TSE181".to_owned()],
+			vec!["1:  08:00".to_owned(), "FÄN / F018\nVertretung".to_owned(), "".to_owned()],
+			vec!["- 09:30".to_owned(), "".to_owned(), "nach Plan".to_owned()],
+			vec!["".to_owned(), "".to_owned(), "".to_owned()],
+		]]);
+	}
+
+	#[test]
+	fn test_parse_csv_multiple_tables() {
+		let csv = "Block,TSP171\n1:  08:00,Vertretung\n- 09:30,\nBlock,IM171\n1:  08:00,\n- 09:30,---\n";
+
+		let tables = parse_csv(csv).unwrap();
+
+		assert_eq!(tables.len(), 2);
+		assert_eq!(tables[1][0], vec!["Block".to_owned(), "IM171".to_owned()]);
+		assert_eq!(tables[1][2], vec!["- 09:30".to_owned(), "---".to_owned()]);
+	}
+
+	#[test]
+	fn test_parse_csv_malformed() {
+		assert_eq!(
+			parse_csv("1:  08:00,Vertretung\n"),
+			Err(ParseError::MalformedCsv {
+				row: Some(0),
+				message: "Row before the first header row starting with 'Block'".to_owned(),
+			})
+		);
+	}
+
+	#[test]
+	fn test_parse_any_detects_format() {
+		let csv = std::fs::read_to_string("./tabula/yes.csv").unwrap();
+		let json = std::fs::read_to_string("./tabula/yes3.csv").unwrap();
+
+		assert_eq!(parse_any(&csv).unwrap(), parse_csv(&csv).unwrap());
+		assert_eq!(parse_any(&json).unwrap(), parse(&json).unwrap());
+	}
+
+	fn cell(top: f64, left: f64, width: f64, text: &str) -> String {
+		format!(r#"{{ "top": {}, "left": {}, "width": {}, "height": 5.0, "text": "{}" }}"#, top, left, width, text)
+	}
+
+	fn empty_cell() -> String {
+		cell(0.0, 0.0, 0.0, "")
+	}
+
+	#[test]
+	fn test_parse_assigns_cells_by_position() {
+		// Tabula put "Aufgabenbetr." into the first class column and left the second one empty
+		let json = format!(
+			r#"[{{ "data": [[{}, {}, {}], [{}, {}, {}], [{}, {}, {}]] }}]"#,
+			cell(10.0, 30.0, 30.0, "Block"), cell(10.0, 100.0, 50.0, "TSP171"), cell(10.0, 180.0, 50.0, "TSE181"),
+			cell(30.0, 30.0, 40.0, "2:  09:50"), cell(30.0, 168.0, 76.0, "Aufgabenbetr."), empty_cell(),
+			cell(50.0, 40.0, 30.0, "- 11:20"), cell(50.0, 100.0, 50.0, "nach Plan"), empty_cell(),
+		);
+
+		let tables = parse(&json).unwrap();
+
+		assert_eq!(tables, vec![vec![
+			vec!["Block".to_owned(), "TSP171".to_owned(), "TSE181".to_owned()],
+			vec!["2:  09:50".to_owned(), "".to_owned(), "Aufgabenbetr.".to_owned()],
+			vec!["- 11:20".to_owned(), "nach Plan".to_owned(), "".to_owned()],
+		]]);
+	}
+
+	#[test]
+	fn test_parse_groups_rows_by_position() {
+		// A three line cell, tabula split it over two rows and put the middle line into its own row
+		let json = format!(
+			r#"[{{ "data": [[{}, {}], [{}, {}], [{}, {}], [{}, {}]] }}]"#,
+			cell(10.0, 30.0, 30.0, "Block"), cell(10.0, 100.0, 50.0, "IM171"),
+			cell(30.0, 30.0, 40.0, "3:  11:40"), cell(28.0, 100.0, 50.0, "MAG nach Plan"),
+			empty_cell(), cell(39.0, 100.0, 50.0, "MAG / C107"),
+			cell(50.0, 40.0, 30.0, "- 13:10"), cell(51.0, 100.0, 50.0, "Vertretung"),
+		);
+
+		let tables = parse(&json).unwrap();
+
+		assert_eq!(tables, vec![vec![
+			vec!["Block".to_owned(), "IM171".to_owned()],
+			vec!["3:  11:40".to_owned(), "MAG nach Plan".to_owned()],
+			vec!["".to_owned(), "MAG / C107".to_owned()],
+			vec!["- 13:10".to_owned(), "Vertretung".to_owned()],
+		]]);
+	}
+
+	#[test]
+	fn test_parse_malformed_row() {
+		let json = r#"[{ "data": [[{ "top": 0.0, "left": 0.0, "width": 0.0, "height": 0.0, "text": "Block" }], [{ "text": 1 }]] }]"#;
+
+		match parse(json) {
+			Err(ParseError::MalformedJson { table, row, .. }) => assert_eq!((table, row), (Some(0), Some(1))),
+			other => panic!("Expected a malformed json error, got {:?}", other),
+		}
+	}
+}
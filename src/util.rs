@@ -1,6 +1,7 @@
 use std::path::Path;
 
 use log::trace;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use crate::TEMP_ROOT_DIR;
@@ -10,6 +11,14 @@ pub fn get_random_name() -> String {
 	format!("{}", Uuid::new_v4())
 }
 
+/// The SHA-256 of the data as lowercase hex
+pub fn sha256_hex(data: &[u8]) -> String {
+	Sha256::digest(data)
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect()
+}
+
 pub fn make_temp_dir() -> String {
 	trace!("Creating temp directory");
 	let temp_dir_name = get_random_name();